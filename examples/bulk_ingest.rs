@@ -0,0 +1,27 @@
+// Loading a large file of `name,salary` lines through the ingest pipeline,
+// with one reader thread and a pool of parsers and inserters.
+//
+//   cargo run --example bulk_ingest
+use concurrent_hash_table::{ingest, SalaryTable};
+use std::fs;
+use std::io::{self, Write};
+use std::thread;
+
+fn main() -> io::Result<()> {
+    let path = std::env::temp_dir().join("cht_example_ingest.csv");
+    let mut file = io::BufWriter::new(fs::File::create(&path)?);
+    for i in 0..100_000 {
+        writeln!(file, "employee{},{}.{:02}", i, 40_000 + i % 1000, i % 100)?;
+    }
+    // Not a record: counted as invalid rather than stopping the load.
+    writeln!(file, "no salary here")?;
+    drop(file);
+
+    let table = SalaryTable::new();
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let stats = ingest::ingest(&table, &path, workers)?;
+    println!("{}", stats);
+    println!("{} records in {} chains", table.len(), table.bucket_count());
+
+    fs::remove_file(&path)
+}
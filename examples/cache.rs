@@ -0,0 +1,27 @@
+// A bounded cache: past its memory limit the table drops its oldest records
+// to make room for new ones.
+//
+//   cargo run --example cache
+use concurrent_hash_table::memory::MemoryPolicy;
+use concurrent_hash_table::{SalaryTable, SearchResult};
+
+fn main() {
+    let mut table = SalaryTable::new();
+    table.set_memory_limit(16 * 1024, MemoryPolicy::Evict);
+
+    for i in 0..1000 {
+        table.insert(&format!("user{}", i), "100".parse().unwrap(), 0);
+    }
+    println!(
+        "{} of 1000 records kept in {} bytes",
+        table.len(),
+        table.memory_usage().total()
+    );
+
+    for name in ["user0", "user999"] {
+        match table.search(name, 0) {
+            SearchResult::Found { record } => println!("hit: {}", record),
+            SearchResult::NotFound { name } => println!("evicted: {}", name),
+        }
+    }
+}
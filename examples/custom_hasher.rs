@@ -0,0 +1,33 @@
+// Picking the hash keys get: a width for a new table, or a live rehash to
+// another while readers and writers carry on.
+//
+//   cargo run --example custom_hasher
+use concurrent_hash_table::hash::HashWidth;
+use concurrent_hash_table::{SalaryTable, SearchResult};
+use std::thread;
+
+fn main() {
+    let mut table = SalaryTable::new();
+    table.set_hash_width(HashWidth::Bits64);
+    for i in 0..10_000 {
+        table.insert(&format!("k{}", i), "1".parse().unwrap(), 0);
+    }
+    println!(
+        "k42 hashes to {:x} with {}",
+        HashWidth::Bits64.hash(b"k42"),
+        table.hash_width().name()
+    );
+
+    thread::scope(|scope| {
+        let moved = scope.spawn(|| table.rehash_with(HashWidth::Bits128, 0));
+        // Every key stays reachable while records move.
+        for i in 0..10_000 {
+            assert!(matches!(
+                table.search(&format!("k{}", i), 0),
+                SearchResult::Found { .. }
+            ));
+        }
+        println!("rehashed {} records", moved.join().unwrap());
+    });
+    println!("now using {}", table.hash_width().name());
+}
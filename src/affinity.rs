@@ -0,0 +1,111 @@
+use std::io;
+use std::sync::OnceLock;
+use std::thread;
+
+// The CPUs given to --pin-cpus, once checked.
+static CPUS: OnceLock<Vec<usize>> = OnceLock::new();
+
+// Pins the calling thread to `cpu`, where the platform allows it.
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+
+    // A glibc cpu_set_t: 1024 CPUs.
+    const MASK_WORDS: usize = 16;
+
+    extern "C" {
+        fn sched_setaffinity(pid: i32, size: usize, mask: *const u64) -> i32;
+    }
+
+    pub fn pin(cpu: usize) -> io::Result<()> {
+        if cpu >= MASK_WORDS * 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "CPU number out of range",
+            ));
+        }
+        let mut mask = [0u64; MASK_WORDS];
+        mask[cpu / 64] |= 1 << (cpu % 64);
+        // Pid 0 is the calling thread; the mask outlives the call.
+        let result = unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    pub fn pin(_cpu: usize) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "CPU pinning is only supported on Linux",
+        ))
+    }
+}
+
+// "0-3,6" is CPUs 0, 1, 2, 3 and 6, in that order.
+pub fn parse_cpus(spec: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in spec.split(',') {
+        let part = part.trim();
+        let (first, last): (usize, usize) = match part.split_once('-') {
+            Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+            None => {
+                let cpu = part.parse().ok()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last {
+            return None;
+        }
+        cpus.extend(first..=last);
+    }
+    Some(cpus)
+}
+
+// Has worker threads run on `cpus` from now on. Each is tried first from a
+// scratch thread, so a CPU the process may not use fails here rather than
+// leaving a worker wherever the OS put it.
+pub fn pin_workers(cpus: Vec<usize>) -> io::Result<()> {
+    for &cpu in cpus.iter() {
+        thread::spawn(move || sys::pin(cpu))
+            .join()
+            .expect("pinning thread panicked")
+            .map_err(|e| io::Error::new(e.kind(), format!("CPU {}: {}", cpu, e)))?;
+    }
+    let _ = CPUS.set(cpus);
+    Ok(())
+}
+
+// The CPUs workers run on, if pinned.
+pub fn pinned() -> Option<&'static [usize]> {
+    CPUS.get().map(Vec::as_slice)
+}
+
+// Pins the calling thread, the `index`th worker, to the next CPU in turn.
+// Does nothing unless pin_workers was called.
+pub fn pin_worker(index: usize) {
+    if let Some(cpus) = pinned() {
+        // Every CPU was tried in pin_workers.
+        let _ = sys::pin(cpus[index % cpus.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cpus, sys};
+
+    #[test]
+    fn test_parse_cpus() {
+        assert_eq!(Some(vec![0, 1, 2, 3, 6]), parse_cpus("0-3,6"));
+        assert_eq!(Some(vec![2]), parse_cpus(" 2 "));
+        for bad in ["", "a", "3-1", "1,", "-2", "1-"] {
+            assert_eq!(None, parse_cpus(bad), "{}", bad);
+        }
+        assert!(sys::pin(usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::sync::Mutex;
+
+// Whether allocations are being counted at all.
+pub const ENABLED: bool = cfg!(feature = "count-allocs");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Allocations {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl Allocations {
+    pub fn since(self, earlier: Allocations) -> Allocations {
+        Allocations {
+            count: self.count - earlier.count,
+            bytes: self.bytes - earlier.bytes,
+        }
+    }
+}
+
+// Wraps the system allocator to count each thread's allocations. Growing a
+// buffer counts as one allocation of its new size.
+#[cfg(feature = "count-allocs")]
+mod counting {
+    use super::Allocations;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local! {
+        static COUNT: Cell<Allocations> = const {
+            Cell::new(Allocations { count: 0, bytes: 0 })
+        };
+    }
+
+    fn note(bytes: usize) {
+        // Fails only while the thread is being torn down.
+        let _ = COUNT.try_with(|count| {
+            let mut allocations = count.get();
+            allocations.count += 1;
+            allocations.bytes += bytes as u64;
+            count.set(allocations);
+        });
+    }
+
+    pub fn thread_allocations() -> Allocations {
+        COUNT.try_with(Cell::get).unwrap_or_default()
+    }
+
+    struct CountingAllocator;
+
+    // SAFETY: every call is passed straight to the system allocator.
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            note(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            note(layout.size());
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            note(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+}
+
+// Allocations this thread has made so far; always zero unless the
+// count-allocs feature is on.
+pub fn thread_allocations() -> Allocations {
+    #[cfg(feature = "count-allocs")]
+    return counting::thread_allocations();
+    #[cfg(not(feature = "count-allocs"))]
+    Allocations::default()
+}
+
+// Allocation totals per operation name, for --stats.
+#[derive(Default)]
+pub struct AllocStats {
+    ops: Mutex<Vec<(&'static str, u64, Allocations)>>,
+}
+
+impl AllocStats {
+    pub fn record(&self, op: &'static str, allocations: Allocations) {
+        let mut ops = self.ops.lock().unwrap();
+        match ops.iter_mut().find(|(name, _, _)| *name == op) {
+            Some((_, count, total)) => {
+                *count += 1;
+                total.count += allocations.count;
+                total.bytes += allocations.bytes;
+            }
+            None => ops.push((op, 1, allocations)),
+        }
+    }
+}
+
+impl fmt::Display for AllocStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops = self.ops.lock().unwrap();
+        if ops.is_empty() {
+            return write!(f, "no operations");
+        }
+        for (i, (op, count, total)) in ops.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{}: {} ops, {:.1} allocations and {} bytes per op",
+                op,
+                count,
+                total.count as f64 / *count as f64,
+                total.bytes / count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{thread_allocations, AllocStats, Allocations, ENABLED};
+
+    #[test]
+    fn test_alloc_stats() {
+        let before = thread_allocations();
+        let buffer = vec![0u8; 1000];
+        let made = thread_allocations().since(before);
+        drop(buffer);
+        if ENABLED {
+            assert!(made.count >= 1 && made.bytes >= 1000);
+        } else {
+            assert_eq!(Allocations::default(), made);
+        }
+
+        let stats = AllocStats::default();
+        stats.record(
+            "INSERT",
+            Allocations {
+                count: 3,
+                bytes: 90,
+            },
+        );
+        stats.record(
+            "INSERT",
+            Allocations {
+                count: 2,
+                bytes: 30,
+            },
+        );
+        stats.record("SEARCH", Allocations::default());
+        assert_eq!(
+            "INSERT: 2 ops, 2.5 allocations and 60 bytes per op\n\
+             SEARCH: 1 ops, 0.0 allocations and 0 bytes per op",
+            stats.to_string()
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::hash_table::{InsertResult, SalaryTable};
+use crate::money::Money;
+
+type Records = Vec<(String, Money)>;
+
+// Collects records from many threads for one bulk load. Each thread fills its
+// own Buffer without touching the others; the builder only sees a buffer when
+// it is dropped, and `build` then hands everything to `bulk_load`, which
+// builds an empty table's chains directly.
+#[derive(Default)]
+pub struct HashTableBuilder {
+    // Dropped buffers, by the order they were handed out.
+    buffers: Mutex<Vec<(usize, Records)>>,
+    handed_out: AtomicUsize,
+}
+
+pub struct Buffer<'a> {
+    builder: &'a HashTableBuilder,
+    order: usize,
+    records: Records,
+}
+
+impl HashTableBuilder {
+    // Records in earlier buffers go in first, so when a key repeats, which
+    // one the conflict policy sees first does not depend on thread timing.
+    pub fn buffer(&self) -> Buffer<'_> {
+        Buffer {
+            builder: self,
+            order: self.handed_out.fetch_add(1, Ordering::Relaxed),
+            records: Vec::new(),
+        }
+    }
+
+    // Results are in buffer order, then push order within each buffer.
+    pub fn build(self, table: &SalaryTable, priority: u32) -> Vec<InsertResult> {
+        let mut buffers = self.buffers.into_inner().unwrap();
+        buffers.sort_unstable_by_key(|(order, _)| *order);
+        let records = buffers.into_iter().flat_map(|(_, records)| records);
+        table.bulk_load(records.collect(), priority)
+    }
+}
+
+impl Buffer<'_> {
+    pub fn push(&mut self, name: String, salary: Money) {
+        self.records.push((name, salary));
+    }
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        let records = mem::take(&mut self.records);
+        self.builder
+            .buffers
+            .lock()
+            .unwrap()
+            .push((self.order, records));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashTableBuilder;
+    use crate::hash_table::{ConflictPolicy, SalaryTable, SearchResult};
+    use crate::logger::ThreadLogger;
+    use crate::view::Reducer;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_builder() {
+        let path = std::env::temp_dir().join("cht_test_builder.log");
+        let logger = Arc::new(ThreadLogger::new(path.to_str().unwrap()));
+        let mut table = SalaryTable::with_logger(Arc::clone(&logger));
+        table.set_conflict_policy(ConflictPolicy::Replace);
+        table.create_view("total", |_| "all".to_string(), Reducer::Sum);
+
+        let builder = HashTableBuilder::default();
+        thread::scope(|scope| {
+            for t in 0..4 {
+                let mut buffer = builder.buffer();
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        buffer.push(format!("k{}", i), t.to_string().parse().unwrap());
+                    }
+                });
+            }
+        });
+        let results = builder.build(&table, 0);
+
+        assert_eq!(4000, results.len());
+        assert_eq!(1000, table.salary_count());
+        // The last buffer handed out wins under Replace.
+        match table.search("k7", 0) {
+            SearchResult::Found { record } => assert_eq!("3", record.salary.to_string()),
+            SearchResult::NotFound { .. } => panic!("k7 missing"),
+        }
+
+        // The view is filled once from the built chains, and agrees with one
+        // that saw every insert and merge.
+        let mut expected = SalaryTable::with_logger(logger);
+        expected.set_conflict_policy(ConflictPolicy::Replace);
+        expected.create_view("total", |_| "all".to_string(), Reducer::Sum);
+        for t in 0..4 {
+            let records = (0..1000)
+                .map(|i| (format!("k{}", i), t.to_string().parse().unwrap()))
+                .collect();
+            expected.insert_many(records, 0);
+        }
+        let rows = |table: &SalaryTable| -> Vec<String> {
+            let rows = table.view("total").unwrap();
+            rows.iter().map(ToString::to_string).collect()
+        };
+        assert_eq!(vec!["all: 3000"], rows(&table));
+        assert_eq!(rows(&expected), rows(&table));
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+
+use crate::hash_table::HashRecord;
+use crate::money::Money;
+use crate::redact::Redaction;
+
+// A single committed mutation, published in the order the table applied it.
+#[derive(Debug, Clone)]
+pub struct ChangeEvent<K = String, V = Money> {
+    pub correlation_id: Option<u64>,
+    pub change: Change<K, V>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Change<K = String, V = Money> {
+    Insert {
+        record: HashRecord<K, V>,
+    },
+    Update {
+        old_record: HashRecord<K, V>,
+        new_record: HashRecord<K, V>,
+    },
+    Delete {
+        record: HashRecord<K, V>,
+    },
+}
+
+impl<K: fmt::Display, V: fmt::Display> Change<K, V> {
+    pub fn render(&self, redaction: Redaction) -> String {
+        match self {
+            Change::Insert { record } => format!("INSERT,{}", redaction.record(record)),
+            Change::Update {
+                old_record,
+                new_record,
+            } => {
+                let mut line = format!("UPDATE,{}", redaction.record(new_record));
+                if let Some(old) = redaction.value(&old_record.salary) {
+                    line.push(',');
+                    line.push_str(&old);
+                }
+                line
+            }
+            Change::Delete { record } => format!("DELETE,{}", redaction.record(record)),
+        }
+    }
+}
+
+impl<K: fmt::Display, V: fmt::Display> ChangeEvent<K, V> {
+    pub fn render(&self, redaction: Redaction) -> String {
+        match self.correlation_id {
+            Some(cid) => format!("{} [cid={}]", self.change.render(redaction), cid),
+            None => self.change.render(redaction),
+        }
+    }
+}
+
+impl ChangeEvent {
+    // Reads back a line rendered without redaction. Lines with masked or
+    // dropped salaries give None; hashed keys come back as the hash text.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (line, correlation_id) = match line
+            .strip_suffix(']')
+            .and_then(|line| line.rsplit_once(" [cid="))
+        {
+            Some((line, cid)) => (line, Some(cid.parse().ok()?)),
+            None => (line, None),
+        };
+        let (kind, fields) = line.split_once(',')?;
+        let change = match kind {
+            "INSERT" => Change::Insert {
+                record: parse_record(fields)?,
+            },
+            "UPDATE" => {
+                let (fields, old) = fields.rsplit_once(',')?;
+                let new_record = parse_record(fields)?;
+                let old_record = HashRecord {
+                    salary: old.parse().ok()?,
+                    ..new_record.clone()
+                };
+                Change::Update {
+                    old_record,
+                    new_record,
+                }
+            }
+            "DELETE" => Change::Delete {
+                record: parse_record(fields)?,
+            },
+            _ => return None,
+        };
+        Some(ChangeEvent {
+            correlation_id,
+            change,
+        })
+    }
+}
+
+// "<hash>,<name>,<salary>"; the name may hold commas.
+fn parse_record(fields: &str) -> Option<HashRecord> {
+    let (hash, rest) = fields.split_once(',')?;
+    let (name, salary) = rest.rsplit_once(',')?;
+    Some(HashRecord {
+        hash: hash.parse().ok()?,
+        name: name.to_string(),
+        salary: salary.parse().ok()?,
+    })
+}
+
+impl<K: fmt::Display, V: fmt::Display> fmt::Display for ChangeEvent<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(Redaction::None))
+    }
+}
+
+// Anything that wants to receive mutations. Called while the table still holds
+// its write lock, so implementations should be quick and must not call back
+// into the table.
+pub trait CdcSink<K = String, V = Money>: Send + Sync {
+    fn publish(&self, event: &ChangeEvent<K, V>);
+}
+
+// Appends one line per event, e.g. "UPDATE,<hash>,<name>,<new>,<old>".
+pub struct FileSink {
+    writer: Mutex<BufWriter<File>>,
+    redaction: Redaction,
+}
+
+impl FileSink {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(FileSink {
+            writer: Mutex::new(BufWriter::new(file)),
+            redaction: Redaction::None,
+        })
+    }
+
+    pub fn set_redaction(&mut self, redaction: Redaction) {
+        self.redaction = redaction;
+    }
+}
+
+impl<K: fmt::Display, V: fmt::Display> CdcSink<K, V> for FileSink {
+    fn publish(&self, event: &ChangeEvent<K, V>) {
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", event.render(self.redaction));
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        if let Ok(writer) = self.writer.get_mut() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileSink;
+    use crate::hash_table::SalaryTable;
+    use crate::logger::{self, ThreadLogger};
+    use crate::redact::Redaction;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_file_sink() {
+        let dir = std::env::temp_dir();
+        let (plain, masked) = (
+            dir.join("cht_test_cdc.txt"),
+            dir.join("cht_test_cdc_masked.txt"),
+        );
+        for (path, redaction) in [(&plain, Redaction::None), (&masked, Redaction::MaskValues)] {
+            let mut sink = FileSink::new(path.to_str().unwrap()).unwrap();
+            sink.set_redaction(redaction);
+            let mut table = SalaryTable::new();
+            table.set_cdc_sink(Box::new(sink));
+            table.insert("a", "10".parse().unwrap(), 0);
+            table.update_salary("a", "20".parse().unwrap(), 0);
+            // Nothing changed, so nothing is published.
+            table.delete("missing", 0);
+            table.delete("a", 0);
+        }
+
+        let hash = SalaryTable::jenkins_one_at_a_time_hash(b"a");
+        let lines = |path| std::fs::read_to_string(path).unwrap();
+        let expected = format!(
+            "INSERT,{hash},a,10\nUPDATE,{hash},a,20,10\nDELETE,{hash},a,20\n",
+            hash = hash
+        );
+        assert_eq!(expected, lines(&plain));
+        let expected = format!(
+            "INSERT,{hash},a,***\nUPDATE,{hash},a,***,***\nDELETE,{hash},a,***\n",
+            hash = hash
+        );
+        assert_eq!(expected, lines(&masked));
+    }
+
+    #[test]
+    fn test_correlation_ids() {
+        let dir = std::env::temp_dir();
+        let (log, cdc) = (
+            dir.join("cht_test_cid.log"),
+            dir.join("cht_test_cid_cdc.txt"),
+        );
+        let mut table =
+            SalaryTable::with_logger(Arc::new(ThreadLogger::new(log.to_str().unwrap())));
+        table.set_cdc_sink(Box::new(FileSink::new(cdc.to_str().unwrap()).unwrap()));
+        logger::set_correlation_id(Some(7));
+        table.insert("a", "10".parse().unwrap(), 0);
+        logger::set_correlation_id(None);
+        assert_eq!(None, logger::correlation_id());
+        table.insert("b", "10".parse().unwrap(), 0);
+        drop(table);
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        let (tagged, untagged): (Vec<&str>, Vec<&str>) =
+            log.lines().partition(|line| line.ends_with(" [cid=7]"));
+        assert!(tagged.iter().any(|line| line.contains("INSERT,")));
+        assert!(untagged.iter().any(|line| line.contains("INSERT,")));
+        assert!(untagged.iter().all(|line| !line.contains("[cid=")));
+
+        let expected = format!(
+            "INSERT,{},a,10 [cid=7]\nINSERT,{},b,10\n",
+            SalaryTable::jenkins_one_at_a_time_hash(b"a"),
+            SalaryTable::jenkins_one_at_a_time_hash(b"b")
+        );
+        assert_eq!(expected, std::fs::read_to_string(&cdc).unwrap());
+    }
+}
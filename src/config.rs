@@ -0,0 +1,247 @@
+use std::fmt;
+use std::path::Path;
+
+// Where a setting's value came from. Sources are applied in this order, so a
+// flag beats an environment variable, which beats the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "config file"),
+            Source::Env => write!(f, "environment"),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+// Every layered setting, its default and what a valid value looks like. Each
+// is also the flag `--<key>`, the variable `CHT_<KEY>` (upper case, `-` as
+// `_`) and the file line `<key> = <value>`.
+const SETTINGS: &[(&str, Option<&str>, &str)] = &[
+    ("log-path", Some("hash.log"), "a path"),
+    ("commands", Some("commands.txt"), "a path"),
+    (
+        "scheduler",
+        Some("strict"),
+        "one of: strict, bands:<width>, pool:<workers>, rate:<per-second>",
+    ),
+    ("hash-width", Some("32"), "32, 64 or 128"),
+    ("buckets", Some("1024"), "a positive number"),
+    ("stripes", Some("1"), "a positive number"),
+    ("max-load-factor", Some("0.75"), "a positive number, or off"),
+    ("print-order", Some("hash"), "one of: hash, insertion, key"),
+    (
+        "on-conflict",
+        Some("reject"),
+        "one of: reject, replace, keep-max, sum",
+    ),
+    ("undo-depth", Some("16"), "a number"),
+    ("slow-op-ms", None, "a number"),
+    (
+        "memory-limit",
+        None,
+        "a byte count, optionally with a k, m or g suffix",
+    ),
+    ("memory-policy", Some("reject"), "reject or evict"),
+    ("read-lease-ms", None, "a number"),
+    ("read-lease-policy", Some("warn"), "warn or abort"),
+    ("ingest", None, "a path"),
+    ("ingest-workers", None, "a positive number"),
+    ("lookups", None, "a path"),
+];
+
+// Read before the other settings to find the config file, so it is not one.
+pub const CONFIG_ENV: &str = "CHT_CONFIG";
+// Used when neither --config nor CHT_CONFIG names a file, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "cht.conf";
+
+struct Setting {
+    key: &'static str,
+    expected: &'static str,
+    value: Option<String>,
+    source: Source,
+}
+
+// The effective value of every setting after layering defaults, the config
+// file, CHT_* variables and flags. Problems are collected rather than returned
+// so every bad value can be reported at once.
+pub struct Config {
+    settings: Vec<Setting>,
+    errors: Vec<String>,
+}
+
+fn env_name(key: &str) -> String {
+    format!("CHT_{}", key.to_uppercase().replace('-', "_"))
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            settings: SETTINGS
+                .iter()
+                .map(|(key, default, expected)| Setting {
+                    key,
+                    expected,
+                    value: default.map(str::to_string),
+                    source: Source::Default,
+                })
+                .collect(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn is_setting(key: &str) -> bool {
+        SETTINGS.iter().any(|(k, _, _)| *k == key)
+    }
+
+    // Returns whether `key` is a setting.
+    pub fn set(&mut self, key: &str, value: &str, source: Source) -> bool {
+        match self.settings.iter_mut().find(|s| s.key == key) {
+            Some(setting) => {
+                setting.value = Some(value.to_string());
+                setting.source = source;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // `key = value` lines; blank lines and lines starting with `#` are skipped.
+    pub fn load_file(&mut self, path: &Path) {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.errors.push(format!("{}: {}", path.display(), e));
+                return;
+            }
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = match line.split_once('=') {
+                Some((key, value)) if self.set(key.trim(), value.trim(), Source::File) => continue,
+                Some((key, _)) => format!("unknown setting: {}", key.trim()),
+                None => "expected <key> = <value>".to_string(),
+            };
+            self.errors
+                .push(format!("{}:{}: {}", path.display(), i + 1, error));
+        }
+    }
+
+    // Takes the variables rather than reading them, so tests can pass their own.
+    pub fn load_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        for (name, value) in vars {
+            if !name.starts_with("CHT_") || name == CONFIG_ENV {
+                continue;
+            }
+            match SETTINGS.iter().find(|(key, _, _)| env_name(key) == name) {
+                Some((key, _, _)) => {
+                    self.set(key, &value, Source::Env);
+                }
+                None => self.errors.push(format!("unknown setting: {}", name)),
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .find(|s| s.key == key)
+            .and_then(|s| s.value.as_deref())
+    }
+
+    // The setting parsed with `parse`. None if it is unset, or if it does not
+    // parse, in which case the error names where the bad value came from.
+    pub fn parse<T>(&mut self, key: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+        let setting = self.settings.iter().find(|s| s.key == key)?;
+        let value = setting.value.as_deref()?;
+        let origin = match setting.source {
+            Source::Default => "default".to_string(),
+            Source::File => format!("{} in config file", key),
+            Source::Env => env_name(key),
+            Source::Cli => format!("--{}", key),
+        };
+        let parsed = parse(value);
+        if parsed.is_none() {
+            let error = format!("{}={}: {} must be {}", origin, value, key, setting.expected);
+            self.errors.push(error);
+        }
+        parsed
+    }
+
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.settings.iter().map(|s| s.key.len()).max().unwrap_or(0);
+        for (i, setting) in self.settings.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{:<width$} = {} ({})",
+                setting.key,
+                setting.value.as_deref().unwrap_or("unset"),
+                setting.source,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Source};
+
+    #[test]
+    fn test_precedence() {
+        let path = std::env::temp_dir().join("cht_test_precedence.conf");
+        std::fs::write(&path, "# comment\nhash-width = 64\nundo-depth = 4\n").unwrap();
+
+        let mut config = Config::new();
+        config.load_file(&path);
+        let env = [
+            ("CHT_UNDO_DEPTH", "8"),
+            ("CHT_LOG_PATH", "run.log"),
+            ("PATH", "/bin"),
+        ];
+        config.load_env(env.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        config.set("log-path", "cli.log", Source::Cli);
+        assert!(config.errors().is_empty());
+
+        assert_eq!(Some("64"), config.get("hash-width"));
+        assert_eq!(Some("8"), config.get("undo-depth"));
+        assert_eq!(Some("cli.log"), config.get("log-path"));
+        assert_eq!(Some("commands.txt"), config.get("commands"));
+        assert_eq!(None, config.get("slow-op-ms"));
+
+        config.load_env([("CHT_UNDO_DEPT".to_string(), "1".to_string())]);
+        config.set("undo-depth", "lots", Source::Env);
+        assert_eq!(
+            None,
+            config.parse("undo-depth", |s| s.parse::<usize>().ok())
+        );
+        assert_eq!(
+            [
+                "unknown setting: CHT_UNDO_DEPT",
+                "CHT_UNDO_DEPTH=lots: undo-depth must be a number"
+            ],
+            config.errors()
+        );
+    }
+}
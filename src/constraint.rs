@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::cdc::Change;
+use crate::hash::HashValue;
+use crate::hash_table::HashRecord;
+use crate::money::Money;
+
+type Derive<K, V> = Box<dyn Fn(&HashRecord<K, V>) -> String + Send + Sync>;
+
+// Requires an attribute derived from each record to be unique across the table.
+// The index maps each derived value to the hash of the record holding it.
+pub struct UniqueConstraint<K = String, V = Money> {
+    name: String,
+    derive: Derive<K, V>,
+    // Only touched while the table's write lock is held.
+    index: Mutex<HashMap<String, HashValue>>,
+}
+
+impl<K, V> UniqueConstraint<K, V> {
+    pub fn new(
+        name: &str,
+        derive: impl Fn(&HashRecord<K, V>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        UniqueConstraint {
+            name: name.to_string(),
+            derive: Box::new(derive),
+            index: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Whether the table would still satisfy the constraint after `change`.
+    pub fn admits(&self, change: &Change<K, V>) -> bool {
+        let index = self.index.lock().unwrap();
+        match change {
+            Change::Insert { record }
+            | Change::Update {
+                new_record: record, ..
+            } => match index.get(&(self.derive)(record)) {
+                Some(hash) => *hash == record.hash,
+                None => true,
+            },
+            Change::Delete { .. } => true,
+        }
+    }
+
+    // Brings the index up to date with a committed change.
+    pub fn apply(&self, change: &Change<K, V>) {
+        let mut index = self.index.lock().unwrap();
+        let mut remove = |record: &HashRecord<K, V>| {
+            let value = (self.derive)(record);
+            if index.get(&value) == Some(&record.hash) {
+                index.remove(&value);
+            }
+        };
+        match change {
+            Change::Insert { .. } => {}
+            Change::Update { old_record, .. } => remove(old_record),
+            Change::Delete { record } => remove(record),
+        }
+        match change {
+            Change::Insert { record }
+            | Change::Update {
+                new_record: record, ..
+            } => {
+                index.insert((self.derive)(record), record.hash);
+            }
+            Change::Delete { .. } => {}
+        }
+    }
+}
+
+impl UniqueConstraint {
+    // No two records may have the same salary.
+    pub fn salary() -> Self {
+        UniqueConstraint::new("unique_salary", |record: &HashRecord| {
+            record.salary.to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UniqueConstraint;
+    use crate::cdc::Change;
+    use crate::hash::HashValue;
+    use crate::hash_table::HashRecord;
+
+    fn record(hash: HashValue, salary: &str) -> HashRecord {
+        HashRecord {
+            hash,
+            name: hash.to_string(),
+            salary: salary.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_unique_salary() {
+        let constraint = UniqueConstraint::salary();
+        constraint.apply(&Change::Insert {
+            record: record(1, "100"),
+        });
+
+        assert!(!constraint.admits(&Change::Insert {
+            record: record(2, "100")
+        }));
+        // A record may keep its own value.
+        assert!(constraint.admits(&Change::Update {
+            old_record: record(1, "100"),
+            new_record: record(1, "100"),
+        }));
+
+        constraint.apply(&Change::Update {
+            old_record: record(1, "100"),
+            new_record: record(1, "200"),
+        });
+        assert!(constraint.admits(&Change::Insert {
+            record: record(2, "100")
+        }));
+        assert!(!constraint.admits(&Change::Insert {
+            record: record(2, "200")
+        }));
+    }
+}
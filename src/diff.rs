@@ -0,0 +1,98 @@
+use std::fmt;
+
+use crate::frozen::FrozenTable;
+use crate::hash_table::HashRecord;
+
+// What changed between two tables, compared by key. Each list is sorted by
+// key so the same two tables always print the same diff.
+#[derive(Default)]
+pub struct TableDiff {
+    pub added: Vec<HashRecord>,
+    pub removed: Vec<HashRecord>,
+    // Old and new record for keys whose salary differs.
+    pub changed: Vec<(HashRecord, HashRecord)>,
+}
+
+impl TableDiff {
+    pub fn between(before: &FrozenTable, after: &FrozenTable) -> Self {
+        let mut diff = TableDiff::default();
+        for record in after.records() {
+            match before.get(&record.name) {
+                None => diff.added.push(record.clone()),
+                Some(old) if old.salary != record.salary => {
+                    diff.changed.push((old.clone(), record.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for record in before.records() {
+            if after.get(&record.name).is_none() {
+                diff.removed.push(record.clone());
+            }
+        }
+        diff.added.sort_by(|a, b| a.name.cmp(&b.name));
+        diff.removed.sort_by(|a, b| a.name.cmp(&b.name));
+        diff.changed.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// One line per difference, then the counts.
+impl fmt::Display for TableDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for record in self.added.iter() {
+            writeln!(f, "+ {}", record)?;
+        }
+        for record in self.removed.iter() {
+            writeln!(f, "- {}", record)?;
+        }
+        for (old, new) in self.changed.iter() {
+            writeln!(f, "~ {} -> {}", old, new)?;
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash_table::SalaryTable;
+    use crate::logger::ThreadLogger;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_diff() {
+        let path = std::env::temp_dir().join("cht_test_diff.log");
+        let logger = Arc::new(ThreadLogger::new(path.to_str().unwrap()));
+        let (before, after) = (
+            SalaryTable::with_logger(logger.clone()),
+            SalaryTable::with_logger(logger),
+        );
+        for (name, salary) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            before.insert(name, salary.parse().unwrap(), 0);
+        }
+        for (name, salary) in [("d", "4"), ("c", "30"), ("a", "1")] {
+            after.insert(name, salary.parse().unwrap(), 0);
+        }
+
+        let diff = before.diff(&after, 0);
+        let names = |records: &[crate::hash_table::HashRecord]| {
+            records.iter().map(|r| r.name.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["d"], names(&diff.added));
+        assert_eq!(vec!["b"], names(&diff.removed));
+        assert_eq!(1, diff.changed.len());
+        assert_eq!("30", diff.changed[0].1.salary.to_string());
+        assert!(diff.to_string().ends_with("1 added, 1 removed, 1 changed"));
+        assert!(before.diff(&before, 0).is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::Mutex;
+
+use crate::cdc::Change;
+use crate::hash_table::{HashRecord, Value};
+use crate::money::Money;
+
+#[derive(Default)]
+struct State {
+    count: usize,
+    // Lowest and highest salary per currency, in first-seen order.
+    bounds: Vec<(Money, Money)>,
+    // Set when a change removed a current bound; cleared by `refresh`.
+    stale: bool,
+}
+
+// Running record count and per-currency salary bounds; values that are not
+// money are only counted. Inserts keep the bounds exact; removing a salary that is a bound only marks them stale, and
+// the next read rebuilds them from a scan.
+#[derive(Default)]
+pub struct Extremes {
+    // Only written while the table's write lock is held.
+    state: Mutex<State>,
+}
+
+impl State {
+    fn add(&mut self, salary: Option<Money>) {
+        self.count += 1;
+        let Some(salary) = salary else {
+            return;
+        };
+        match self
+            .bounds
+            .iter_mut()
+            .find(|(min, _)| min.currency() == salary.currency())
+        {
+            Some((min, max)) => {
+                if salary < *min {
+                    *min = salary;
+                }
+                if salary > *max {
+                    *max = salary;
+                }
+            }
+            None => self.bounds.push((salary, salary)),
+        }
+    }
+
+    fn remove(&mut self, salary: Option<Money>) {
+        self.count -= 1;
+        let Some(salary) = salary else {
+            return;
+        };
+        if self
+            .bounds
+            .iter()
+            .any(|(min, max)| salary == *min || salary == *max)
+        {
+            self.stale = true;
+        }
+    }
+}
+
+impl Extremes {
+    pub fn apply<K, V: Value>(&self, change: &Change<K, V>) {
+        let mut state = self.state.lock().unwrap();
+        match change {
+            Change::Insert { record } => state.add(record.salary.money()),
+            Change::Update {
+                old_record,
+                new_record,
+            } => {
+                state.remove(old_record.salary.money());
+                state.add(new_record.salary.money());
+            }
+            Change::Delete { record } => state.remove(record.salary.money()),
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.state.lock().unwrap().stale
+    }
+
+    // Rebuilds the bounds from every record in the table.
+    pub fn refresh<'a, K: 'a, V: Value>(
+        &self,
+        records: impl Iterator<Item = &'a HashRecord<K, V>>,
+    ) {
+        let mut state = State::default();
+        for record in records {
+            state.add(record.salary.money());
+        }
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn count(&self) -> usize {
+        self.state.lock().unwrap().count
+    }
+
+    pub fn min(&self) -> Vec<Money> {
+        let state = self.state.lock().unwrap();
+        state.bounds.iter().map(|(min, _)| *min).collect()
+    }
+
+    pub fn max(&self) -> Vec<Money> {
+        let state = self.state.lock().unwrap();
+        state.bounds.iter().map(|(_, max)| *max).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extremes;
+    use crate::cdc::Change;
+    use crate::hash_table::HashRecord;
+    use crate::money::Money;
+
+    fn record(name: &str, salary: &str) -> HashRecord {
+        HashRecord {
+            hash: 0,
+            name: name.to_string(),
+            salary: salary.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_lazy_bounds() {
+        let money = |s: &str| s.parse::<Money>().unwrap();
+        let extremes = Extremes::default();
+        let records = [record("a", "10"), record("b", "30"), record("c", "5 EUR")];
+        for record in records.iter() {
+            extremes.apply(&Change::Insert {
+                record: record.clone(),
+            });
+        }
+        assert_eq!(vec![money("10"), money("5 EUR")], extremes.min());
+        assert!(!extremes.is_stale());
+
+        extremes.apply(&Change::Delete {
+            record: records[1].clone(),
+        });
+        assert!(extremes.is_stale());
+        extremes.refresh(records.iter().filter(|r| r.name != "b"));
+        assert_eq!(vec![money("10"), money("5 EUR")], extremes.max());
+        assert_eq!(2, extremes.count());
+    }
+}
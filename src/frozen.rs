@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::hash::HashWidth;
+use crate::hash_table::HashRecord;
+use crate::money::Money;
+
+// A read-only copy of the table for serving lookups once it is built. The
+// records sit in one array sorted by hash, so a lookup is a binary search and
+// there is nothing to lock: any number of threads can share one.
+pub struct FrozenTable {
+    width: HashWidth,
+    records: Vec<HashRecord>,
+}
+
+impl FrozenTable {
+    // `records` must be hashed with `width`.
+    pub fn new(width: HashWidth, mut records: Vec<HashRecord>) -> Self {
+        records.sort_unstable_by(|a, b| (a.hash, &a.name).cmp(&(b.hash, &b.name)));
+        records.shrink_to_fit();
+        FrozenTable { width, records }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&HashRecord> {
+        let hash = self.width.hash(key.as_bytes());
+        let start = self.records.partition_point(|r| r.hash < hash);
+        self.records[start..]
+            .iter()
+            .take_while(|r| r.hash == hash)
+            .find(|r| r.name == key)
+    }
+
+    // In hash order.
+    pub fn records(&self) -> &[HashRecord] {
+        &self.records
+    }
+}
+
+// A copy-on-write fork of a frozen table, for what-if salary changes over a
+// large table. Reads fall through to the base, which forks of the same
+// snapshot share; a record is copied into the fork the first time it
+// changes. The table and the fork never see each other's changes. Only
+// salaries change: a fork cannot insert or delete records.
+pub struct Fork {
+    base: Arc<FrozenTable>,
+    // Records changed in this fork, by key.
+    changed: HashMap<String, HashRecord>,
+}
+
+impl Fork {
+    pub fn new(base: Arc<FrozenTable>) -> Self {
+        Fork {
+            base,
+            changed: HashMap::new(),
+        }
+    }
+
+    // Returns whether the key exists.
+    pub fn set_salary(&mut self, key: &str, salary: Money) -> bool {
+        if let Some(record) = self.changed.get_mut(key) {
+            record.salary = salary;
+            return true;
+        }
+        match self.base.get(key) {
+            Some(record) => {
+                let record = HashRecord {
+                    salary,
+                    ..record.clone()
+                };
+                self.changed.insert(key.to_string(), record);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // In hash order, with this fork's changes.
+    pub fn records(&self) -> impl Iterator<Item = &HashRecord> {
+        self.base
+            .records()
+            .iter()
+            .map(|record| self.changed.get(&record.name).unwrap_or(record))
+    }
+
+    // How many records the fork has its own copy of.
+    pub fn copied(&self) -> usize {
+        self.changed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash_table::SalaryTable;
+    use crate::logger::ThreadLogger;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_freeze() {
+        let path = std::env::temp_dir().join("cht_test_freeze.log");
+        let table = SalaryTable::with_logger(Arc::new(ThreadLogger::new(path.to_str().unwrap())));
+        // Different keys with the same Jenkins hash.
+        for (name, salary) in [("k11310", "1"), ("b", "2"), ("k19528", "3")] {
+            table.insert(name, salary.parse().unwrap(), 0);
+        }
+        let frozen = table.freeze(0);
+        table.delete("b", 0);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    assert_eq!("3", frozen.get("k19528").unwrap().salary.to_string());
+                    assert_eq!("1", frozen.get("k11310").unwrap().salary.to_string());
+                    assert!(frozen.get("b").is_some());
+                    assert!(frozen.get("c").is_none());
+                });
+            }
+        });
+        assert_eq!(3, frozen.records().len());
+    }
+
+    #[test]
+    fn test_fork() {
+        let path = std::env::temp_dir().join("cht_test_fork.log");
+        let table = SalaryTable::with_logger(Arc::new(ThreadLogger::new(path.to_str().unwrap())));
+        table.insert("a", "1".parse().unwrap(), 0);
+        table.insert("b", "2".parse().unwrap(), 0);
+
+        let mut fork = table.fork(0);
+        assert!(fork.set_salary("a", "10".parse().unwrap()));
+        assert!(fork.set_salary("a", "11".parse().unwrap()));
+        assert!(!fork.set_salary("c", "3".parse().unwrap()));
+        table.update_salary("b", "20".parse().unwrap(), 0);
+
+        let salaries = |records: Vec<&crate::hash_table::HashRecord>| {
+            let mut salaries: Vec<_> = records
+                .iter()
+                .map(|r| format!("{}={}", r.name, r.salary))
+                .collect();
+            salaries.sort();
+            salaries
+        };
+        assert_eq!(vec!["a=11", "b=2"], salaries(fork.records().collect()));
+        assert_eq!(1, fork.copied());
+        let records = table.get_all_records(0);
+        assert_eq!(vec!["a=1", "b=20"], salaries(records.iter().collect()));
+    }
+}
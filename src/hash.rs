@@ -0,0 +1,220 @@
+use std::borrow::Cow;
+use std::io::{self, Read};
+
+// Record hashes are stored at the widest supported width; narrower hashers
+// leave the high bits zero, so a 32-bit hash prints exactly as before.
+pub type HashValue = u128;
+
+// Which hash keys get, from --hash-width.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashWidth {
+    // Jenkins one-at-a-time, as the table has always used.
+    #[default]
+    Bits32,
+    // FNV-1a, for tables large enough that 32 bits collide.
+    Bits64,
+    Bits128,
+}
+
+impl HashWidth {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "32" => Some(HashWidth::Bits32),
+            "64" => Some(HashWidth::Bits64),
+            "128" => Some(HashWidth::Bits128),
+            _ => None,
+        }
+    }
+
+    // As written in the run header.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashWidth::Bits32 => "jenkins-one-at-a-time",
+            HashWidth::Bits64 => "fnv-1a-64",
+            HashWidth::Bits128 => "fnv-1a-128",
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashWidth::Bits32 => Hasher::Jenkins(Jenkins::new()),
+            HashWidth::Bits64 => Hasher::Fnv64(FNV64_OFFSET),
+            HashWidth::Bits128 => Hasher::Fnv128(FNV128_OFFSET),
+        }
+    }
+
+    pub fn hash(self, bytes: &[u8]) -> HashValue {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finish()
+    }
+}
+
+// The bytes a table key is hashed as. Text is hashed as its UTF-8 bytes, so
+// a String key's hash is the one --hash prints for it; byte strings as
+// themselves; integers as their little-endian bytes.
+pub trait KeyBytes {
+    fn key_bytes(&self) -> Cow<'_, [u8]>;
+}
+
+impl KeyBytes for str {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl KeyBytes for String {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        self.as_str().key_bytes()
+    }
+}
+
+impl KeyBytes for [u8] {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl KeyBytes for Vec<u8> {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl KeyBytes for u32 {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.to_le_bytes().to_vec())
+    }
+}
+
+impl KeyBytes for u64 {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.to_le_bytes().to_vec())
+    }
+}
+
+impl KeyBytes for i64 {
+    fn key_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.to_le_bytes().to_vec())
+    }
+}
+
+const FNV64_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV64_PRIME: u64 = 0x0000_0100_0000_01b3;
+const FNV128_OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const FNV128_PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+// A hash in progress at one of the supported widths.
+#[derive(Debug, Clone, Copy)]
+pub enum Hasher {
+    Jenkins(Jenkins),
+    Fnv64(u64),
+    Fnv128(u128),
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Jenkins(jenkins) => jenkins.update(bytes),
+            Hasher::Fnv64(state) => {
+                for &byte in bytes {
+                    *state = (*state ^ byte as u64).wrapping_mul(FNV64_PRIME);
+                }
+            }
+            Hasher::Fnv128(state) => {
+                for &byte in bytes {
+                    *state = (*state ^ byte as u128).wrapping_mul(FNV128_PRIME);
+                }
+            }
+        }
+    }
+
+    pub fn finish(&self) -> HashValue {
+        match self {
+            Hasher::Jenkins(jenkins) => jenkins.finish() as HashValue,
+            Hasher::Fnv64(state) => *state as HashValue,
+            Hasher::Fnv128(state) => *state,
+        }
+    }
+}
+
+// Bob Jenkins' one-at-a-time hash, fed a chunk at a time. Hashing a key in
+// pieces gives the same value as hashing it whole, which is what the table
+// stores as each record's hash.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Jenkins {
+    state: u32,
+}
+
+impl Jenkins {
+    pub fn new() -> Self {
+        Jenkins::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = self.state.wrapping_add(byte as u32);
+            self.state = self.state.wrapping_add(self.state << 10);
+            self.state ^= self.state >> 6;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        let mut hash = self.state;
+        hash = hash.wrapping_add(hash << 3);
+        hash ^= hash >> 11;
+        hash = hash.wrapping_add(hash << 15);
+        hash
+    }
+}
+
+pub fn jenkins_one_at_a_time(bytes: &[u8]) -> u32 {
+    let mut hasher = Jenkins::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+// Hashes everything `reader` yields without holding it all in memory.
+pub fn hash_reader(width: HashWidth, mut reader: impl Read) -> io::Result<HashValue> {
+    let mut hasher = width.hasher();
+    let mut buf = [0; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(hasher.finish()),
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_reader, jenkins_one_at_a_time, HashWidth, Jenkins};
+
+    #[test]
+    fn test_streaming() {
+        let input = b"The quick brown fox jumps over the lazy dog";
+        assert_eq!(0x519e91f5, jenkins_one_at_a_time(input));
+
+        let mut hasher = Jenkins::new();
+        for chunk in input.chunks(5) {
+            hasher.update(chunk);
+        }
+        assert_eq!(0x519e91f5, hasher.finish());
+        assert_eq!(
+            0x519e91f5,
+            hash_reader(HashWidth::Bits32, &input[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(0xca2e9442, HashWidth::Bits32.hash(b"a"));
+        assert_eq!(0xaf63dc4c8601ec8c, HashWidth::Bits64.hash(b"a"));
+        assert_eq!(
+            0xd228cb696f1a8caf78912b704e4a8964,
+            HashWidth::Bits128.hash(b"a")
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::money::Money;
+
+// Length of the bar for the fullest bucket.
+const BAR_COLUMNS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub min: Money,
+    // Rounded toward zero to the cent, as is the median of an even count.
+    pub mean: Money,
+    pub median: Money,
+    pub max: Money,
+}
+
+// Salaries counted into buckets `width` wide, each starting at a multiple of
+// `width`. Only salaries in the width's currency are counted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SalaryHistogram {
+    pub width: Money,
+    // Each bucket's lowest salary and how many fell in it, lowest first.
+    // Empty buckets are left out.
+    pub buckets: Vec<(Money, usize)>,
+    // Values in another currency, or not money at all.
+    pub skipped: usize,
+    // None when nothing was counted.
+    pub summary: Option<Summary>,
+}
+
+impl SalaryHistogram {
+    // A width under one cent counts each cent on its own.
+    pub fn build(width: Money, salaries: impl IntoIterator<Item = Option<Money>>) -> Self {
+        let currency = width.currency();
+        let step = width.minor().max(1);
+        let mut amounts = Vec::new();
+        let mut skipped = 0;
+        for salary in salaries {
+            match salary {
+                Some(salary) if salary.currency() == currency => amounts.push(salary.minor()),
+                _ => skipped += 1,
+            }
+        }
+
+        let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+        for minor in amounts.iter() {
+            *counts.entry(minor.div_euclid(step)).or_default() += 1;
+        }
+        let money = |minor| Money::from_minor(minor, currency);
+        let buckets = counts
+            .into_iter()
+            .map(|(bucket, count)| (money(bucket * step), count))
+            .collect();
+
+        amounts.sort_unstable();
+        let summary = (!amounts.is_empty()).then(|| {
+            let total: i128 = amounts.iter().map(|&minor| minor as i128).sum();
+            let mid = amounts.len() / 2;
+            let median = match amounts.len() % 2 {
+                1 => amounts[mid],
+                _ => ((amounts[mid - 1] as i128 + amounts[mid] as i128) / 2) as i64,
+            };
+            Summary {
+                min: money(amounts[0]),
+                mean: money((total / amounts.len() as i128) as i64),
+                median: money(median),
+                max: money(amounts[amounts.len() - 1]),
+            }
+        });
+
+        SalaryHistogram {
+            width: money(step),
+            buckets,
+            skipped,
+            summary,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.buckets.iter().map(|(_, count)| count).sum()
+    }
+}
+
+// One bar per bucket, longest for the fullest, then the summary.
+impl fmt::Display for SalaryHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(summary) = self.summary else {
+            return write!(f, "Salary report: no salaries");
+        };
+        write!(f, "Salary report ({} per bucket):", self.width)?;
+        let labels: Vec<String> = self
+            .buckets
+            .iter()
+            .map(|(floor, _)| {
+                let ceiling = Money::from_minor(
+                    floor.minor().saturating_add(self.width.minor()),
+                    floor.currency(),
+                );
+                format!("{}..{}", floor, ceiling)
+            })
+            .collect();
+        let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+        let fullest = self.buckets.iter().map(|(_, count)| *count).max().unwrap();
+        for (label, (_, count)) in labels.iter().zip(self.buckets.iter()) {
+            let bar = (count * BAR_COLUMNS / fullest).max(1);
+            write!(
+                f,
+                "\n{:>width$} |{} {}",
+                label,
+                "#".repeat(bar),
+                count,
+                width = width
+            )?;
+        }
+        write!(
+            f,
+            "\n{} salaries: min {}, mean {}, median {}, max {}",
+            self.count(),
+            summary.min,
+            summary.mean,
+            summary.median,
+            summary.max
+        )?;
+        if self.skipped > 0 {
+            let currency = self.width.currency();
+            write!(
+                f,
+                " ({} not in {} skipped)",
+                self.skipped,
+                currency.as_str()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SalaryHistogram;
+    use crate::money::Money;
+
+    #[test]
+    fn test_histogram() {
+        let money = |s: &str| s.parse::<Money>().unwrap();
+        let salaries = ["5", "15", "12", "31", "-2", "7 EUR"].map(|s| Some(money(s)));
+        let histogram = SalaryHistogram::build(money("10"), salaries.into_iter().chain([None]));
+
+        let buckets: Vec<(String, usize)> = histogram
+            .buckets
+            .iter()
+            .map(|(floor, count)| (floor.to_string(), *count))
+            .collect();
+        let expected = [("-10", 1), ("0", 1), ("10", 2), ("30", 1)];
+        assert_eq!(expected.map(|(f, c)| (f.to_string(), c)).to_vec(), buckets);
+        assert_eq!(2, histogram.skipped);
+
+        let summary = histogram.summary.unwrap();
+        assert_eq!(money("-2"), summary.min);
+        assert_eq!(money("12.20"), summary.mean);
+        assert_eq!(money("12"), summary.median);
+        assert_eq!(money("31"), summary.max);
+
+        let report = histogram.to_string();
+        assert!(report.contains("\n10..20 |######################################## 2"));
+        assert!(report.contains("\n30..40 |#################### 1"));
+        assert!(report.ends_with("max 31 (2 not in USD skipped)"));
+
+        let empty = SalaryHistogram::build(money("10"), []);
+        assert_eq!("Salary report: no salaries", empty.to_string());
+    }
+}
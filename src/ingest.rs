@@ -0,0 +1,270 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::builder::HashTableBuilder;
+use crate::hash_table::{InsertResult, SalaryTable};
+use crate::logger;
+use crate::money::Money;
+use crate::unicode;
+
+// Lines per batch handed between stages, and records per insert_many call.
+const BATCH_LINES: usize = 4096;
+// Batches each queue holds before its producer blocks.
+const QUEUE_BATCHES: usize = 8;
+// Lines between progress reports.
+const PROGRESS_LINES: usize = 1_000_000;
+// What bulk-load operations are tagged with in the log and stats.
+const INGEST_TAG: &str = "ingest";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    pub lines: usize,
+    pub inserted: usize,
+    pub merged: usize,
+    // Duplicates under --on-conflict reject, constraint violations, vetoes
+    // and inserts past the memory limit.
+    pub rejected: usize,
+    // Lines that are neither `name,salary` nor a command file insert.
+    pub invalid: usize,
+}
+
+impl fmt::Display for IngestStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Ingested {} lines: {} inserted, {} merged, {} rejected, {} invalid",
+            self.lines, self.inserted, self.merged, self.rejected, self.invalid
+        )
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    lines: AtomicUsize,
+    inserted: AtomicUsize,
+    merged: AtomicUsize,
+    rejected: AtomicUsize,
+    invalid: AtomicUsize,
+}
+
+impl Counters {
+    fn count(&self, result: &InsertResult) {
+        let counter = match result {
+            InsertResult::Success { .. } => &self.inserted,
+            InsertResult::Merged { .. } => &self.merged,
+            _ => &self.rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(self) -> IngestStats {
+        IngestStats {
+            lines: self.lines.into_inner(),
+            inserted: self.inserted.into_inner(),
+            merged: self.merged.into_inner(),
+            rejected: self.rejected.into_inner(),
+            invalid: self.invalid.into_inner(),
+        }
+    }
+}
+
+// `name,salary`, or `insert,name,salary,priority` so an existing command
+// file can be loaded too. Blank lines and `#` comments are skipped.
+fn parse_line(line: &str) -> Option<Option<(String, Money)>> {
+    let line = unicode::normalize_keys(line.trim());
+    if line.is_empty() || line.starts_with('#') {
+        return Some(None);
+    }
+    let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+    let (name, salary) = match parts.as_slice() {
+        [name, salary] => (name, salary),
+        ["insert", name, salary, _] => (name, salary),
+        _ => return None,
+    };
+    Some(Some((name.to_string(), salary.parse().ok()?)))
+}
+
+// Shares one receiver between a stage's workers.
+fn next_batch<T>(receiver: &Mutex<Receiver<T>>) -> Option<T> {
+    receiver.lock().unwrap().recv().ok()
+}
+
+// Loads a large file of records before the command file runs. One thread
+// reads batches of lines, `workers` threads parse them and `workers` more
+// pass the parsed batches to insert_many; bounded queues between the stages
+// keep only a few batches in memory. Progress goes to stderr.
+pub fn ingest(table: &SalaryTable, path: &Path, workers: usize) -> io::Result<IngestStats> {
+    let file = File::open(path)?;
+    let counters = Counters::default();
+    let (line_tx, line_rx) = mpsc::sync_channel::<Vec<String>>(QUEUE_BATCHES);
+    let (record_tx, record_rx) = mpsc::sync_channel::<Vec<(String, Money)>>(QUEUE_BATCHES);
+    let (line_rx, record_rx) = (Mutex::new(line_rx), Mutex::new(record_rx));
+
+    let read: io::Result<()> = thread::scope(|scope| {
+        for _ in 0..workers {
+            let record_tx = record_tx.clone();
+            let (line_rx, counters) = (&line_rx, &counters);
+            scope.spawn(move || {
+                while let Some(lines) = next_batch(line_rx) {
+                    let mut records = Vec::with_capacity(lines.len());
+                    for line in lines {
+                        match parse_line(&line) {
+                            Some(record) => records.extend(record),
+                            None => {
+                                counters.invalid.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    if record_tx.send(records).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        // The writers stop once every parser has dropped its sender.
+        drop(record_tx);
+
+        for _ in 0..workers {
+            let (record_rx, counters) = (&record_rx, &counters);
+            scope.spawn(move || {
+                logger::set_tag(Some(INGEST_TAG));
+                while let Some(records) = next_batch(record_rx) {
+                    for result in table.insert_many(records, 0) {
+                        counters.count(&result);
+                    }
+                }
+            });
+        }
+
+        let mut lines = BufReader::new(file).lines();
+        let mut batch = Vec::with_capacity(BATCH_LINES);
+        loop {
+            let line = lines.next().transpose()?;
+            let done = line.is_none();
+            batch.extend(line);
+            if batch.len() == BATCH_LINES || (done && !batch.is_empty()) {
+                let read = counters.lines.fetch_add(batch.len(), Ordering::Relaxed) + batch.len();
+                if read / PROGRESS_LINES > (read - batch.len()) / PROGRESS_LINES {
+                    eprintln!("Ingest: {} lines read from {}", read, path.display());
+                }
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_LINES));
+                if line_tx.send(full).is_err() {
+                    break;
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        // Lets the parsers finish; the scope then waits for every stage.
+        drop(line_tx);
+        Ok(())
+    });
+    read?;
+    Ok(counters.stats())
+}
+
+// For a cold load into a table nothing else is using yet: reads the whole
+// file, parses a slice of it on each of `workers` threads into its own
+// builder buffer, then inserts everything under one write lock. Faster than
+// `ingest`, but holds the file and its parsed records in memory at once.
+// When a key repeats, the later line is the one that conflicts.
+pub fn load(table: &SalaryTable, path: &Path, workers: usize) -> io::Result<IngestStats> {
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().collect();
+    let counters = Counters::default();
+    counters.lines.store(lines.len(), Ordering::Relaxed);
+
+    let builder = HashTableBuilder::default();
+    thread::scope(|scope| {
+        for lines in lines.chunks(lines.len().div_ceil(workers).max(1)) {
+            let (mut buffer, counters) = (builder.buffer(), &counters);
+            scope.spawn(move || {
+                for line in lines {
+                    match parse_line(line) {
+                        Some(Some((name, salary))) => buffer.push(name, salary),
+                        Some(None) => {}
+                        None => {
+                            counters.invalid.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let caller_tag = logger::tag();
+    logger::set_tag(Some(INGEST_TAG));
+    for result in builder.build(table, 0) {
+        counters.count(&result);
+    }
+    logger::set_tag(caller_tag);
+    Ok(counters.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ingest, IngestStats};
+    use crate::hash_table::{ConflictPolicy, SalaryTable, SearchResult};
+    use crate::logger::ThreadLogger;
+    use crate::unicode;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_ingest() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("cht_test_ingest.csv");
+        let mut text = String::from("# name,salary\n\n");
+        for i in 0..10_000 {
+            text.push_str(&format!("name{},{}\n", i, i));
+        }
+        text.push_str("insert,name7,70,3\nname8,80\nname9\nname10,lots\n");
+        std::fs::write(&path, text).unwrap();
+
+        let logger = Arc::new(ThreadLogger::new(
+            dir.join("cht_test_ingest.log").to_str().unwrap(),
+        ));
+        let mut table = SalaryTable::with_logger(logger);
+        table.set_conflict_policy(ConflictPolicy::Replace);
+        let stats = ingest(&table, &path, 3).unwrap();
+
+        let expected = IngestStats {
+            lines: 10_006,
+            inserted: 10_000,
+            merged: 2,
+            rejected: 0,
+            invalid: 2,
+        };
+        assert_eq!(expected, stats);
+        assert_eq!(10_000, table.salary_count());
+    }
+
+    #[test]
+    fn test_normalize_keys() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("cht_test_ingest_nfc.csv");
+        // "éclair" precomposed, then as e + combining acute.
+        std::fs::write(&path, "\u{e9}clair,10\ne\u{301}clair,20\n").unwrap();
+
+        let logger = Arc::new(ThreadLogger::new(
+            dir.join("cht_test_ingest_nfc.log").to_str().unwrap(),
+        ));
+        let mut table = SalaryTable::with_logger(logger);
+        table.set_conflict_policy(ConflictPolicy::Replace);
+        unicode::set_normalize_keys(true);
+        let stats = ingest(&table, &path, 1).unwrap();
+        unicode::set_normalize_keys(false);
+
+        assert_eq!((1, 1), (stats.inserted, stats.merged));
+        assert_eq!(1, table.salary_count());
+        assert!(matches!(
+            table.search("\u{e9}clair", 0),
+            SearchResult::Found { record } if record.salary == "20".parse().unwrap()
+        ));
+    }
+}
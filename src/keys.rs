@@ -0,0 +1,56 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyError {
+    Empty,
+    ControlCharacter { position: usize, character: char },
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Empty => write!(f, "key is empty"),
+            KeyError::ControlCharacter {
+                position,
+                character,
+            } => write!(
+                f,
+                "key contains control character U+{:04X} at position {}",
+                *character as u32, position
+            ),
+        }
+    }
+}
+
+// Every key goes through here before it reaches the table, so whatever input
+// path a key came from it is held to the same rules.
+pub fn validate(key: &str) -> Result<(), KeyError> {
+    if key.is_empty() {
+        return Err(KeyError::Empty);
+    }
+    match key.chars().enumerate().find(|(_, c)| c.is_control()) {
+        Some((position, character)) => Err(KeyError::ControlCharacter {
+            position,
+            character,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, KeyError};
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(Ok(()), validate("Zoë Ω"));
+        assert_eq!(Err(KeyError::Empty), validate(""));
+        assert_eq!(
+            Err(KeyError::ControlCharacter {
+                position: 3,
+                character: '\t'
+            }),
+            validate("Bob\tSmith")
+        );
+    }
+}
@@ -0,0 +1,38 @@
+// lib.rs
+// The table and everything it is built from. The concurrent-hash-table
+// binary is one user: it parses command files and runs them against a
+// SalaryTable.
+pub mod allocs;
+pub mod builder;
+pub mod cdc;
+pub mod constraint;
+pub mod diff;
+mod extremes;
+pub mod frozen;
+pub mod hash;
+pub mod hash_table;
+pub mod histogram;
+pub mod ingest;
+pub mod lock_order;
+pub mod logger;
+pub mod memory;
+pub mod merkle;
+pub mod metrics;
+pub mod money;
+#[cfg(feature = "ordered")]
+mod ordered;
+mod priority_lock;
+pub mod redact;
+pub mod similar;
+pub mod timeline;
+pub mod trigger;
+pub mod unicode;
+mod unicode_tables;
+pub mod view;
+
+pub use hash_table::{
+    AdjustResult, BytesKey, Conditional, ConditionalResult, ConflictPolicy, DeleteResult, Entry,
+    EntryResult, GetStatus, HashRecord, HashTable, HashTableConfig, InsertResult, Key, Lookup,
+    SalaryTable, SearchResult, UndoResult, UpdateResult, UpsertResult, Value,
+};
+pub use logger::ThreadLogger;
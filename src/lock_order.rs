@@ -0,0 +1,201 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::logger::LockType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockAction {
+    Acquire,
+    Release,
+}
+
+#[derive(Debug, Clone)]
+pub struct LockEvent {
+    pub thread: String,
+    pub lock: String,
+    pub lock_type: LockType,
+    pub action: LockAction,
+}
+
+// Every lock event in the order it was recorded.
+pub struct LockTrace {
+    events: Mutex<Vec<LockEvent>>,
+}
+
+// Two threads that took the same pair of locks in opposite orders.
+pub struct Inversion {
+    pub first: String,
+    pub second: String,
+    pub forward_thread: String,
+    pub reverse_thread: String,
+}
+
+// A lock acquired by one thread while another still held it for writing.
+pub struct Overlap {
+    pub lock: String,
+    pub writer: String,
+    pub other: String,
+}
+
+#[derive(Default)]
+pub struct LockOrderReport {
+    pub events: usize,
+    pub inversions: Vec<Inversion>,
+    pub overlaps: Vec<Overlap>,
+}
+
+impl fmt::Display for LockOrderReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Lock order analysis: {} events, {} inversions, {} overlapping write sections",
+            self.events,
+            self.inversions.len(),
+            self.overlaps.len()
+        )?;
+        for inversion in &self.inversions {
+            write!(
+                f,
+                "\nINVERSION {} -> {} (thread {}) vs {} -> {} (thread {})",
+                inversion.first,
+                inversion.second,
+                inversion.forward_thread,
+                inversion.second,
+                inversion.first,
+                inversion.reverse_thread
+            )?;
+        }
+        for overlap in &self.overlaps {
+            write!(
+                f,
+                "\nOVERLAP on {}: thread {} acquired while thread {} held it for write",
+                overlap.lock, overlap.other, overlap.writer
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for LockTrace {
+    fn default() -> Self {
+        LockTrace::new()
+    }
+}
+
+impl LockTrace {
+    pub fn new() -> Self {
+        LockTrace {
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, event: LockEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    // Replays the trace, tracking what each thread holds. Taking B while holding
+    // A adds an A -> B edge; an edge seen in both directions is an inversion.
+    pub fn analyze(&self) -> LockOrderReport {
+        let events = self.events.lock().unwrap();
+        let mut report = LockOrderReport {
+            events: events.len(),
+            ..LockOrderReport::default()
+        };
+
+        let mut held: HashMap<&str, Vec<&LockEvent>> = HashMap::new();
+        let mut edges: HashMap<(&str, &str), &str> = HashMap::new();
+        let mut reported: HashSet<(&str, &str)> = HashSet::new();
+
+        for event in events.iter() {
+            match event.action {
+                LockAction::Acquire => {
+                    for (thread, holding) in held.iter() {
+                        for h in holding.iter() {
+                            if *thread != event.thread.as_str()
+                                && h.lock == event.lock
+                                && h.lock_type == LockType::Write
+                            {
+                                report.overlaps.push(Overlap {
+                                    lock: event.lock.clone(),
+                                    writer: thread.to_string(),
+                                    other: event.thread.clone(),
+                                });
+                            }
+                        }
+                    }
+
+                    let holding = held.entry(event.thread.as_str()).or_default();
+                    for h in holding.iter().filter(|h| h.lock != event.lock) {
+                        let edge = (h.lock.as_str(), event.lock.as_str());
+                        edges.entry(edge).or_insert(event.thread.as_str());
+
+                        let reverse = (edge.1, edge.0);
+                        if let Some(reverse_thread) = edges.get(&reverse) {
+                            if reported.insert(reverse) && reported.insert(edge) {
+                                report.inversions.push(Inversion {
+                                    first: reverse.0.to_string(),
+                                    second: reverse.1.to_string(),
+                                    forward_thread: reverse_thread.to_string(),
+                                    reverse_thread: event.thread.clone(),
+                                });
+                            }
+                        }
+                    }
+                    holding.push(event);
+                }
+                LockAction::Release => {
+                    if let Some(holding) = held.get_mut(event.thread.as_str()) {
+                        if let Some(pos) = holding.iter().rposition(|h| h.lock == event.lock) {
+                            holding.remove(pos);
+                        }
+                    }
+                }
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockAction, LockEvent, LockTrace};
+    use crate::logger::LockType;
+
+    fn event(thread: &str, lock: &str, action: LockAction) -> LockEvent {
+        LockEvent {
+            thread: thread.to_string(),
+            lock: lock.to_string(),
+            lock_type: LockType::Write,
+            action,
+        }
+    }
+
+    #[test]
+    fn test_inversion_and_overlap() {
+        use LockAction::{Acquire, Release};
+
+        let trace = LockTrace::new();
+        for e in [
+            event("1", "a", Acquire),
+            event("1", "b", Acquire),
+            event("1", "b", Release),
+            event("1", "a", Release),
+            event("2", "b", Acquire),
+            event("3", "b", Acquire),
+            event("2", "a", Acquire),
+            event("2", "a", Release),
+            event("2", "b", Release),
+        ] {
+            trace.record(e);
+        }
+
+        let report = trace.analyze();
+        assert_eq!(1, report.inversions.len());
+        assert_eq!("a", report.inversions[0].first);
+        assert_eq!("2", report.inversions[0].reverse_thread);
+        assert_eq!(1, report.overlaps.len());
+        assert_eq!("3", report.overlaps[0].other);
+    }
+}
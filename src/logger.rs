@@ -1,26 +1,129 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
-use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-fn current_timestamp() -> u128 {
+use crate::lock_order::{LockAction, LockEvent, LockOrderReport, LockTrace};
+use crate::redact::Redaction;
+use crate::timeline::{Span, Timeline};
+
+pub fn current_timestamp() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_micros()
 }
 
+thread_local! {
+    static CORRELATION_ID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+// Tag everything the calling thread logs (and publishes to CDC) with a
+// correlation ID until it is cleared with `None`.
+pub fn set_correlation_id(id: Option<u64>) {
+    CORRELATION_ID.with(|cid| cid.set(id));
+}
+
+pub fn correlation_id() -> Option<u64> {
+    CORRELATION_ID.with(|cid| cid.get())
+}
+
+thread_local! {
+    static TAG: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+// Every tag used in the run. There are only ever a handful, so each is
+// leaked once and shared.
+static TAGS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+// Label what the calling thread does (say "ingest" or "api") in its log lines
+// and in the per-tag stats, until it is cleared with `None`.
+pub fn set_tag(tag: Option<&str>) {
+    let tag = tag.map(|tag| {
+        let mut tags = TAGS.lock().unwrap();
+        match tags.iter().find(|t| **t == tag) {
+            Some(t) => *t,
+            None => {
+                let t: &'static str = Box::leak(tag.into());
+                tags.push(t);
+                t
+            }
+        }
+    });
+    TAG.with(|t| t.set(tag));
+}
+
+pub fn tag() -> Option<&'static str> {
+    TAG.with(|t| t.get())
+}
+
+// Configuration that produced a hash.log, written as its first line.
+pub struct RunHeader {
+    pub backend: &'static str,
+    pub buckets: usize,
+    pub stripes: usize,
+    pub hash: &'static str,
+    pub hash_seed: Option<u64>,
+    pub threads: usize,
+    // None when the threads are not pinned.
+    pub cpus: Option<Vec<usize>>,
+    pub command_file: String,
+    pub command_file_hash: u32,
+}
+
+impl fmt::Display for RunHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "HEADER version={} backend={} buckets={} stripes={} hash={} seed={} threads={} cpus={} commands={} commands_hash={:08x}",
+            env!("CARGO_PKG_VERSION"),
+            self.backend,
+            self.buckets,
+            self.stripes,
+            self.hash,
+            self.hash_seed
+                .map_or("none".to_string(), |seed| seed.to_string()),
+            self.threads,
+            self.cpus.as_ref().map_or("any".to_string(), |cpus| {
+                cpus.iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            self.command_file,
+            self.command_file_hash
+        )
+    }
+}
+
+// Name used in lock traces for the table's own lock.
+pub const TABLE_LOCK: &str = "table";
+
+// Name used in lock traces for one stripe of the table's lock.
+pub fn stripe_lock(stripe: usize) -> String {
+    format!("{} stripe {}", TABLE_LOCK, stripe)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LockType {
     Read,
     Write,
 }
 
 pub enum LogMessage {
+    // The whole table's lock, or every stripe of it.
     Acquire(LockType),
     Release(LockType),
+    // A single stripe, when the table has more than one.
+    AcquireStripe(LockType, usize),
+    ReleaseStripe(LockType, usize),
     Custom(String),
 }
 
@@ -29,64 +132,276 @@ pub struct ThreadLogger {
     handle: Option<thread::JoinHandle<()>>,
     acquisitions: AtomicUsize,
     releases: AtomicUsize,
+    // Optional ThreadId -> log ID mapping used by `log` to label lines.
+    registry: Mutex<HashMap<ThreadId, String>>,
+    redaction: Redaction,
+    // Lines sent but not yet written by the logging thread.
+    pending: Arc<AtomicUsize>,
+    high_watermark: Option<usize>,
+    degraded: AtomicBool,
+    dropped: AtomicUsize,
+    lock_trace: Option<LockTrace>,
+    timeline: Option<Timeline>,
 }
 
 impl ThreadLogger {
     pub fn new(path: &str) -> Self {
         let (tx, rx) = mpsc::channel::<String>();
+        let mut logger = ThreadLogger::disabled();
 
         // Spawn the actual logging thread
         let path = path.to_string();
-        let handle = thread::spawn(move || logging_thread(rx, path));
+        let thread_pending = Arc::clone(&logger.pending);
+        let handle = thread::spawn(move || logging_thread(rx, path, thread_pending));
+        logger.sender = Some(tx);
+        logger.handle = Some(handle);
+        logger
+    }
 
+    // Writes no lines and starts no thread, for a table used without a log.
+    // Lock acquisitions and releases are still counted.
+    pub fn disabled() -> Self {
         ThreadLogger {
-            sender: Some(tx),
-            handle: Some(handle),
+            sender: None,
+            handle: None,
             acquisitions: AtomicUsize::new(0),
             releases: AtomicUsize::new(0),
+            registry: Mutex::new(HashMap::new()),
+            redaction: Redaction::None,
+            pending: Arc::new(AtomicUsize::new(0)),
+            high_watermark: None,
+            degraded: AtomicBool::new(false),
+            dropped: AtomicUsize::new(0),
+            lock_trace: None,
+            timeline: None,
+        }
+    }
+
+    // Keep every acquire/release in memory for `analyze_lock_order`.
+    pub fn enable_lock_trace(&mut self) {
+        self.lock_trace = Some(LockTrace::new());
+    }
+
+    // For code outside the table that takes its own locks around table calls.
+    // Events are labelled with the calling thread, as in `log`.
+    pub fn record_lock_event(&self, lock: &str, lock_type: LockType, action: LockAction) {
+        if let Some(trace) = self.lock_trace.as_ref() {
+            trace.record(LockEvent {
+                thread: self.current_thread_label(),
+                lock: lock.to_string(),
+                lock_type,
+                action,
+            });
+        }
+    }
+
+    // Flags lock-order inversions and overlapping write sections in the trace.
+    // Empty unless `enable_lock_trace` was called.
+    pub fn analyze_lock_order(&self) -> LockOrderReport {
+        self.lock_trace
+            .as_ref()
+            .map(|trace| trace.analyze())
+            .unwrap_or_default()
+    }
+
+    // Keep how long each thread waited for and held each lock, for an ASCII
+    // timeline at the end of the run.
+    pub fn enable_timeline(&mut self) {
+        self.timeline = Some(Timeline::new());
+    }
+
+    // A lock `thread` asked for at `requested`, got at `acquired` and is
+    // releasing now.
+    pub fn record_span(
+        &self,
+        thread: String,
+        lock: &str,
+        lock_type: LockType,
+        requested: Instant,
+        acquired: Instant,
+    ) {
+        if let Some(timeline) = self.timeline.as_ref() {
+            timeline.record(Span {
+                thread,
+                lock: lock.to_string(),
+                lock_type,
+                requested,
+                acquired,
+                released: Instant::now(),
+            });
+        }
+    }
+
+    pub fn timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_ref()
+    }
+
+    // Once this many lines are waiting to be written, lock acquire/release lines
+    // are dropped (still counted) until the backlog drains to half the mark.
+    pub fn set_high_watermark(&mut self, lines: usize) {
+        self.high_watermark = Some(lines);
+    }
+
+    // Checks the backlog and emits the degraded/restored markers on transitions.
+    fn lock_events_throttled(&self) -> bool {
+        let Some(high) = self.high_watermark else {
+            return false;
+        };
+        let pending = self.pending.load(Ordering::SeqCst);
+
+        if self.degraded.load(Ordering::SeqCst) {
+            if pending > high / 2 {
+                return true;
+            }
+            if self.degraded.swap(false, Ordering::SeqCst) {
+                let dropped = self.dropped.swap(0, Ordering::SeqCst);
+                self.send(format!(
+                    "{}: LOGGING RESTORED, {} LOCK EVENTS DROPPED\n",
+                    current_timestamp(),
+                    dropped
+                ));
+            }
+            false
+        } else if pending >= high {
+            if !self.degraded.swap(true, Ordering::SeqCst) {
+                self.send(format!(
+                    "{}: DEGRADED LOGGING, BACKLOG OF {} LINES\n",
+                    current_timestamp(),
+                    pending
+                ));
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn send(&self, line: String) {
+        if let Some(sender) = self.sender.as_ref() {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if sender.send(line).is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Policy callers should apply to names and salaries before logging them.
+    pub fn set_redaction(&mut self, redaction: Redaction) {
+        self.redaction = redaction;
+    }
+
+    pub fn redaction(&self) -> Redaction {
+        self.redaction
+    }
+
+    // Label the calling thread's later `log` lines with `label`: its command's
+    // priority, say, or "<priority>.<worker>" for one of a command's workers.
+    pub fn register_thread(&self, label: impl fmt::Display) {
+        self.registry
+            .lock()
+            .unwrap()
+            .insert(thread::current().id(), label.to_string());
+    }
+
+    pub fn unregister_thread(&self) {
+        self.registry
+            .lock()
+            .unwrap()
+            .remove(&thread::current().id());
+    }
+
+    // Label for the calling thread: its registered label, else its name, else
+    // the opaque std ThreadId.
+    pub fn current_thread_label(&self) -> String {
+        let current = thread::current();
+        if let Some(label) = self.registry.lock().unwrap().get(&current.id()) {
+            return label.clone();
+        }
+        match current.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", current.id()),
         }
     }
 
+    // Like `log_id`, but labels the line with the thread that is actually
+    // executing rather than a caller-supplied ID.
+    pub fn log(&self, msg: LogMessage) {
+        let label = self.current_thread_label();
+        self.log_labeled(&label, msg);
+    }
+
     pub fn log_id(&self, thread_id: u32, msg: LogMessage) {
+        self.log_labeled(&thread_id.to_string(), msg);
+    }
+
+    fn log_labeled(&self, thread_id: &str, msg: LogMessage) {
         let timestamp = current_timestamp();
 
-        let msg_string = match msg {
-            LogMessage::Acquire(lock_type) => {
-                self.acquisitions.fetch_add(1, Ordering::SeqCst);
-                match lock_type {
-                    LockType::Read => {
-                        format!("{}: THREAD {} READ LOCK ACQUIRED\n", timestamp, thread_id)
-                    }
-                    LockType::Write => {
-                        format!("{}: THREAD {} WRITE LOCK ACQUIRED\n", timestamp, thread_id)
-                    }
-                }
+        let (lock_type, action, stripe) = match msg {
+            LogMessage::Acquire(lock_type) => (lock_type, LockAction::Acquire, None),
+            LogMessage::Release(lock_type) => (lock_type, LockAction::Release, None),
+            LogMessage::AcquireStripe(lock_type, stripe) => {
+                (lock_type, LockAction::Acquire, Some(stripe))
             }
-            LogMessage::Release(lock_type) => {
-                self.releases.fetch_add(1, Ordering::SeqCst);
-                match lock_type {
-                    LockType::Read => {
-                        format!("{}: THREAD {} READ LOCK RELEASED\n", timestamp, thread_id)
-                    }
-                    LockType::Write => {
-                        format!("{}: THREAD {} WRITE LOCK RELEASED\n", timestamp, thread_id)
-                    }
-                }
+            LogMessage::ReleaseStripe(lock_type, stripe) => {
+                (lock_type, LockAction::Release, Some(stripe))
             }
             LogMessage::Custom(msg) => {
-                format!("{}: THREAD {} {}\n", timestamp, thread_id, msg)
+                self.send_line(format!("{}: THREAD {} {}", timestamp, thread_id, msg));
+                return;
             }
         };
 
-        if let Some(sender) = self.sender.as_ref() {
-            let _ = sender.send(msg_string);
+        if let Some(trace) = self.lock_trace.as_ref() {
+            trace.record(LockEvent {
+                thread: thread_id.to_string(),
+                lock: stripe.map_or(TABLE_LOCK.to_string(), stripe_lock),
+                lock_type,
+                action,
+            });
+        }
+
+        match action {
+            LockAction::Acquire => self.acquisitions.fetch_add(1, Ordering::SeqCst),
+            LockAction::Release => self.releases.fetch_add(1, Ordering::SeqCst),
+        };
+        if self.lock_events_throttled() {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+        let lock = match lock_type {
+            LockType::Read => "READ",
+            LockType::Write => "WRITE",
+        };
+        let done = match action {
+            LockAction::Acquire => "ACQUIRED",
+            LockAction::Release => "RELEASED",
+        };
+        let mut line = format!("{}: THREAD {} {} LOCK {}", timestamp, thread_id, lock, done);
+        if let Some(stripe) = stripe {
+            line.push_str(&format!(" ON STRIPE {}", stripe));
         }
+        self.send_line(line);
     }
 
-    pub fn log_str<S: Into<String>>(&self, msg: S) {
-        if let Some(sender) = self.sender.as_ref() {
-            let _ = sender.send(msg.into());
+    // Tags the line with the calling thread's correlation ID and tag, if any.
+    fn send_line(&self, mut line: String) {
+        if let Some(cid) = correlation_id() {
+            line.push_str(&format!(" [cid={}]", cid));
         }
+        if let Some(tag) = tag() {
+            line.push_str(&format!(" [tag={}]", tag));
+        }
+        line.push('\n');
+        self.send(line);
+    }
+
+    pub fn log_header(&self, header: &RunHeader) {
+        self.send(format!("{}\n", header));
+    }
+
+    pub fn log_str<S: Into<String>>(&self, msg: S) {
+        self.send(msg.into());
     }
 
     pub fn get_acquisition_count(&self) -> usize {
@@ -97,12 +412,13 @@ impl ThreadLogger {
     }
 }
 
-fn logging_thread(rx: Receiver<String>, path: String) {
+fn logging_thread(rx: Receiver<String>, path: String, pending: Arc<AtomicUsize>) {
     let file = File::create(path).unwrap();
     let mut writer = BufWriter::new(file);
 
     for msg in rx {
         writer.write_all(msg.as_bytes()).unwrap();
+        pending.fetch_sub(1, Ordering::SeqCst);
     }
 
     // When all senders are dropped, the loop ends and we flush/close the file
@@ -119,3 +435,91 @@ impl Drop for ThreadLogger {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{LockType, LogMessage, RunHeader, ThreadLogger};
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_run_header() {
+        let mut header = RunHeader {
+            backend: "buckets",
+            buckets: 1024,
+            stripes: 4,
+            hash: "jenkins-one-at-a-time",
+            hash_seed: None,
+            threads: 3,
+            cpus: None,
+            command_file: "commands.txt".to_string(),
+            command_file_hash: 0xab,
+        };
+        let expected = |seed: &str, cpus: &str| {
+            format!(
+                "HEADER version={} backend=buckets buckets=1024 stripes=4 \
+                 hash=jenkins-one-at-a-time seed={} threads=3 cpus={} \
+                 commands=commands.txt commands_hash=000000ab",
+                env!("CARGO_PKG_VERSION"),
+                seed,
+                cpus
+            )
+        };
+        assert_eq!(expected("none", "any"), header.to_string());
+
+        header.hash_seed = Some(42);
+        header.cpus = Some(vec![0, 2]);
+        assert_eq!(expected("42", "0,2"), header.to_string());
+    }
+
+    #[test]
+    fn test_high_watermark() {
+        // Lines stay queued in `rx`, standing in for a writer that has stalled.
+        let (tx, rx) = mpsc::channel();
+        let mut logger = ThreadLogger::disabled();
+        logger.sender = Some(tx);
+        logger.set_high_watermark(4);
+
+        for i in 0..4 {
+            logger.log_id(1, LogMessage::Custom(format!("LINE {}", i)));
+        }
+        logger.log_id(1, LogMessage::Acquire(LockType::Write));
+        logger.log_id(1, LogMessage::Release(LockType::Write));
+        // Dropped lines are still counted.
+        assert_eq!(1, logger.get_acquisition_count());
+        assert_eq!(1, logger.get_release_count());
+
+        // The writer catches up to half the mark: lock lines come back.
+        let backlog: Vec<String> = rx.try_iter().collect();
+        logger.pending.fetch_sub(3, Ordering::SeqCst);
+        logger.log_id(1, LogMessage::Acquire(LockType::Read));
+        assert_eq!(2, logger.get_acquisition_count());
+
+        assert_eq!(5, backlog.len());
+        assert!(backlog[3].ends_with("THREAD 1 LINE 3\n"));
+        assert!(backlog[4].ends_with(": DEGRADED LOGGING, BACKLOG OF 4 LINES\n"));
+        let resumed: Vec<String> = rx.try_iter().collect();
+        assert_eq!(2, resumed.len());
+        assert!(resumed[0].ends_with(": LOGGING RESTORED, 2 LOCK EVENTS DROPPED\n"));
+        assert!(resumed[1].ends_with("THREAD 1 READ LOCK ACQUIRED\n"));
+    }
+
+    #[test]
+    fn test_thread_labels() {
+        let path = std::env::temp_dir().join("cht_test_thread_labels.log");
+        let logger = ThreadLogger::new(path.to_str().unwrap());
+
+        logger.register_thread(7);
+        assert_eq!("7", logger.current_thread_label());
+        logger.unregister_thread();
+
+        let named = thread::Builder::new()
+            .name("worker".to_string())
+            .spawn(move || logger.current_thread_label())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!("worker", named);
+    }
+}
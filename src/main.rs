@@ -1,54 +1,748 @@
 // main.rs
-mod hash_table;
-mod logger;
+mod affinity;
+mod config;
+mod keys;
+mod output;
+mod plugin;
+mod preprocess;
+mod query;
+mod replay;
+mod scheduler;
 
-use hash_table::{DeleteResult, HashTable, InsertResult, SearchResult, UpdateResult};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::sync::{Arc, Condvar, Mutex};
+use concurrent_hash_table::{
+    allocs, cdc, constraint, frozen, hash, hash_table, histogram, ingest, lock_order, logger,
+    memory, merkle, metrics, money, redact, similar, trigger, unicode, view,
+};
+
+use hash_table::{
+    AdjustResult, Conditional, ConditionalResult, ConflictPolicy, DeleteResult, End, HashRecord,
+    HashTableConfig, InsertResult, IterationOrder, LeasePolicy, SalaryTable, SearchResult,
+    UndoResult, UpdateResult, UpsertResult,
+};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cdc::{ChangeEvent, FileSink};
+use crate::config::{Config, Source};
+use crate::constraint::UniqueConstraint;
+use crate::frozen::FrozenTable;
+
+use crate::hash::HashWidth;
+use crate::lock_order::LockAction;
+use crate::logger::{LockType, LogMessage, RunHeader, ThreadLogger};
+use crate::memory::MemoryPolicy;
+use crate::metrics::Slo;
+
+use crate::money::{Money, OverflowPolicy};
+use crate::output::{CommandOutcome, OutcomeSender, OutputCollector, OutputOrder};
+use crate::plugin::{CommandContext, CommandRegistry, CustomCommand};
+
+use crate::preprocess::preprocess;
+use crate::query::Query;
+use crate::redact::Redaction;
+use crate::replay::Replay;
+use crate::scheduler::{assign_turns, Scheduler, SchedulerKind, TiePolicy};
+use crate::trigger::{FollowUp, TriggerAction};
+use crate::view::Reducer;
 
-use crate::logger::{LogMessage, ThreadLogger};
+// Name of the scheduler's turn mutex in lock traces.
+const TURN_LOCK: &str = "turn";
+// How much longer --teach makes every table operation hold its lock.
+const TEACH_HOLD: Duration = Duration::from_millis(20);
+const TIMELINE_COLUMNS: usize = 72;
 
+#[derive(Clone)]
 enum Command {
-    Insert { name: String, salary: u32 },
-    Delete { name: String },
-    Update { name: String, salary: u32 },
-    Search { name: String },
+    Insert {
+        name: String,
+        salary: Money,
+        // None uses the table's --on-conflict policy.
+        policy: Option<ConflictPolicy>,
+    },
+    Delete {
+        name: String,
+    },
+    // Deletes the oldest or newest record.
+    Pop {
+        end: End,
+    },
+    Update {
+        name: String,
+        salary: Money,
+    },
+    // Updates the salary only while it is still `expected`.
+    UpdateIf {
+        name: String,
+        expected: Money,
+        salary: Money,
+    },
+    // Inserts the record, or replaces the salary if the name is taken.
+    Upsert {
+        name: String,
+        salary: Money,
+    },
+    Adjust {
+        name: String,
+        delta: Money,
+        policy: OverflowPolicy,
+    },
+    // Raises every salary in [min, max] by `basis_points` / 100 percent.
+    Raise {
+        basis_points: i64,
+        min: Money,
+        max: Money,
+    },
+    Search {
+        name: String,
+    },
+    // A record with its timestamps and access count.
+    Inspect {
+        name: String,
+    },
     Print,
+    // Every key in sorted order.
+    Keys,
+    // Re-inserts the most recently deleted record.
+    Undo,
+    // Records that `undo` can bring back, newest first.
+    Deleted,
+    // The table's approximate memory use.
+    Memory,
+    // Moves every record to another hash function while the table stays in
+    // use.
+    Rehash {
+        width: HashWidth,
+    },
+    // Bulk-loads a file into a fresh table, then swaps it in for this one.
+    Reload {
+        path: String,
+    },
+    // A command from the registry, with the fields between its name and the
+    // priority.
+    Custom {
+        args: Vec<String>,
+        command: CustomCommand,
+    },
+    // Record count and lowest and highest salaries, from running totals.
+    MinMax,
+    // Salaries bucketed `width` apart, drawn as bars, with summary stats.
+    Report {
+        width: Money,
+    },
+    // Pairs of keys within `max_distance` edits of each other.
+    Similar {
+        max_distance: usize,
+    },
+    // Records with keys in `from..to`, in key order.
+    #[cfg(feature = "ordered")]
+    Range {
+        from: String,
+        to: String,
+    },
+    Query {
+        query: Query,
+    },
+    View {
+        name: String,
+    },
+    // Holds the turn for `ms` before passing it on.
+    Sleep {
+        ms: u64,
+    },
+    // Holds the turn until every earlier command has finished.
+    Barrier,
+    AssertExists {
+        name: String,
+    },
+    AssertSalary {
+        name: String,
+        salary: Money,
+    },
+    AssertCount {
+        count: usize,
+    },
+    If {
+        name: String,
+        conditional: Conditional,
+    },
+    // Reports what `command` would touch instead of running it.
+    Explain {
+        text: String,
+        command: Box<Command>,
+    },
+}
+
+impl Command {
+    // The record key the command works on, if any.
+    fn key(&self) -> Option<&str> {
+        match self {
+            Command::Insert { name, .. }
+            | Command::Delete { name }
+            | Command::Update { name, .. }
+            | Command::UpdateIf { name, .. }
+            | Command::Upsert { name, .. }
+            | Command::Adjust { name, .. }
+            | Command::Search { name }
+            | Command::Inspect { name }
+            | Command::AssertExists { name }
+            | Command::AssertSalary { name, .. }
+            | Command::If { name, .. } => Some(name),
+            Command::Raise { .. }
+            | Command::Pop { .. }
+            | Command::Print
+            | Command::Keys
+            | Command::Undo
+            | Command::Deleted
+            | Command::Memory
+            | Command::Rehash { .. }
+            | Command::Reload { .. }
+            | Command::Custom { .. }
+            | Command::MinMax
+            | Command::Report { .. }
+            | Command::Similar { .. }
+            | Command::Query { .. }
+            | Command::View { .. }
+            | Command::Sleep { .. }
+            | Command::Barrier
+            | Command::AssertCount { .. } => None,
+            Command::Explain { command, .. } => command.key(),
+            #[cfg(feature = "ordered")]
+            Command::Range { .. } => None,
+        }
+    }
+
+    // The table lock the command takes, if any.
+    fn lock(&self) -> Option<LockType> {
+        match self {
+            Command::Insert { .. }
+            | Command::Delete { .. }
+            | Command::Pop { .. }
+            | Command::Update { .. }
+            | Command::UpdateIf { .. }
+            | Command::Upsert { .. }
+            | Command::Adjust { .. }
+            | Command::Raise { .. }
+            | Command::Undo
+            | Command::Rehash { .. }
+            | Command::Reload { .. }
+            | Command::If { .. } => Some(LockType::Write),
+            Command::Search { .. }
+            | Command::Inspect { .. }
+            | Command::Print
+            | Command::Keys
+            | Command::Report { .. }
+            | Command::Similar { .. }
+            | Command::Query { .. }
+            | Command::AssertExists { .. }
+            | Command::AssertSalary { .. }
+            | Command::AssertCount { .. }
+            | Command::Explain { .. } => Some(LockType::Read),
+            Command::View { .. }
+            | Command::MinMax
+            | Command::Deleted
+            | Command::Memory
+            | Command::Sleep { .. }
+            | Command::Barrier => None,
+            Command::Custom { command, .. } => command.lock,
+            #[cfg(feature = "ordered")]
+            Command::Range { .. } => Some(LockType::Read),
+        }
+    }
 }
 
 struct CommandWithPriority {
     command: Command,
     priority: u32,
+    correlation_id: u64,
+    // From the optional `xN`, `concurrent` and `tag=` annotations before the
+    // priority.
+    repeat: usize,
+    concurrent: bool,
+    tag: Option<String>,
 }
 
-struct TurnManager {
-    current_turn: Mutex<u32>,
-    condvar: Condvar,
+// Command-line flags. Unrecognised arguments (e.g. the "main" in `cargo run main`)
+// are ignored so the original invocation keeps working.
+#[derive(Default)]
+struct Options {
+    cdc_path: Option<String>,
+    correlation_ids: bool,
+    slow_op_ms: Option<u64>,
+    read_lease_ms: Option<u64>,
+    read_lease_policy: LeasePolicy,
+    log_redaction: Redaction,
+    cdc_redaction: Redaction,
+    log_high_watermark: Option<usize>,
+    log_header: bool,
+    stats: bool,
+    lock_analysis: bool,
+    // Print and log a stripe count fitted to the run's lock waits.
+    tune_stripes: bool,
+    ties: TiePolicy,
+    scheduler: SchedulerKind,
+    output_order: OutputOrder,
+    validate_keys: bool,
+    // Put keys in NFC wherever they are read.
+    normalize_keys: bool,
+    unique_salary: bool,
+    protected: Vec<String>,
+    headcount: Option<String>,
+    views: Vec<(String, String, Reducer)>,
+    slos: Vec<Slo>,
+    teach: bool,
+    track_access: bool,
+    priority_locks: bool,
+    // CPUs for command and concurrent worker threads, taken in turn.
+    pin_cpus: Option<Vec<usize>>,
+    on_conflict: ConflictPolicy,
+    // A file (or "-" for stdin) to print the hash of instead of running.
+    hash_input: Option<String>,
+    hash_width: HashWidth,
+    print_order: IterationOrder,
+    undo_depth: Option<usize>,
+    log_path: String,
+    command_file: String,
+    // A file of records to bulk-load before the command file runs.
+    ingest: Option<String>,
+    ingest_workers: Option<usize>,
+    buckets: Option<usize>,
+    stripes: Option<usize>,
+    // None never grows the buckets.
+    max_load_factor: Option<f64>,
+    // Two record files to compare instead of running.
+    diff: Option<(String, String)>,
+    // Two record files to bring back in line instead of running.
+    anti_entropy: Option<(String, String)>,
+    // A --cdc file to step through instead of running, and the step to stop at.
+    debug_replay: Option<(String, Option<usize>)>,
+    // A file of keys to look up in a frozen copy once the command file is done.
+    lookups: Option<String>,
+    memory_limit: Option<usize>,
+    memory_policy: MemoryPolicy,
 }
 
-impl TurnManager {
-    fn new(start_turn: u32) -> Self {
-        TurnManager {
-            current_turn: Mutex::new(start_turn),
-            condvar: Condvar::new(),
+fn parse_args() -> Options {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1).peekable();
+    // Settings from flags, applied over the other layers once all are read.
+    let mut cli = Vec::new();
+    let mut config_path = None;
+    let mut show_config = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cdc" => {
+                options.cdc_path = Some(args.next().expect("--cdc requires a file path"));
+            }
+            "--correlation-ids" => options.correlation_ids = true,
+            "--redact-log" => options.log_redaction = parse_redaction(args.next()),
+            "--redact-cdc" => options.cdc_redaction = parse_redaction(args.next()),
+            "--log-header" => options.log_header = true,
+            "--stats" => options.stats = true,
+            "--lock-analysis" => options.lock_analysis = true,
+            "--tune-stripes" => options.tune_stripes = true,
+            "--teach" => options.teach = true,
+            "--track-access" => options.track_access = true,
+            "--priority-locks" => options.priority_locks = true,
+            // --pin-cpus <list>, e.g. 0-3,6
+            "--pin-cpus" => {
+                let spec = args.next().expect("--pin-cpus requires a CPU list");
+                let cpus =
+                    affinity::parse_cpus(&spec).expect("--pin-cpus requires a CPU list like 0-3,6");
+                options.pin_cpus = Some(cpus);
+            }
+            "--hash" => {
+                options.hash_input = Some(args.next().expect("--hash requires a file path or -"));
+            }
+            "--validate-keys" => options.validate_keys = true,
+            "--normalize-keys" => options.normalize_keys = true,
+            "--protect" => options
+                .protected
+                .push(args.next().expect("--protect requires a name")),
+            "--headcount" => {
+                options.headcount = Some(args.next().expect("--headcount requires a name"));
+            }
+            "--view" => {
+                let spec = args
+                    .next()
+                    .expect("--view requires <name>=<group>:<reducer>");
+                let (name, rest) = spec
+                    .split_once('=')
+                    .expect("--view requires <name>=<group>:<reducer>");
+                let (group, reducer) = rest
+                    .split_once(':')
+                    .expect("--view requires <name>=<group>:<reducer>");
+                let reducer = Reducer::parse(reducer).expect("--view reducer must be count or sum");
+                options
+                    .views
+                    .push((name.to_string(), group.to_string(), reducer));
+            }
+            // --slo <op>:<target us>[:<window s>], once per operation to watch.
+            "--slo" => {
+                let spec = args
+                    .next()
+                    .expect("--slo requires <op>:<target us>[:<window s>]");
+                let slo = Slo::parse(&spec)
+                    .expect("--slo requires <op>:<target us>[:<window s>], window 1-60s");
+                options.slos.push(slo);
+            }
+            "--unique" => match args.next().as_deref() {
+                Some("salary") => options.unique_salary = true,
+                _ => panic!("--unique supports: salary"),
+            },
+            "--ties" => {
+                options.ties = args
+                    .next()
+                    .as_deref()
+                    .and_then(TiePolicy::parse)
+                    .expect("--ties must be one of: file, concurrent, random[:<seed>]");
+            }
+            "--output-order" => {
+                options.output_order = args
+                    .next()
+                    .as_deref()
+                    .and_then(OutputOrder::parse)
+                    .expect("--output-order must be completion or submission");
+            }
+            "--log-high-watermark" => {
+                let lines = args.next().expect("--log-high-watermark requires a value");
+                options.log_high_watermark = Some(
+                    lines
+                        .parse()
+                        .expect("--log-high-watermark must be a number"),
+                );
+            }
+            "--config" => {
+                config_path = Some(args.next().expect("--config requires a file path"));
+            }
+            // anti-entropy <source> <replica>: record files in the --ingest
+            // format; the replica file is rewritten to match the source.
+            "anti-entropy" => match (args.next(), args.next()) {
+                (Some(source), Some(replica)) => options.anti_entropy = Some((source, replica)),
+                _ => panic!("anti-entropy requires a source and a replica record file"),
+            },
+            // debug-replay <cdc file> [<step>]: prints the table after every
+            // change the file records, up to `step`.
+            "debug-replay" => {
+                let path = args.next().expect("debug-replay requires a CDC file");
+                let last = args.next_if(|arg| arg.parse::<usize>().is_ok());
+                options.debug_replay = Some((path, last.map(|step| step.parse().unwrap())));
+            }
+            // diff <before> <after>: record files in the --ingest format.
+            "diff" => match (args.next(), args.next()) {
+                (Some(before), Some(after)) => options.diff = Some((before, after)),
+                _ => panic!("diff requires two record files"),
+            },
+            "config" => match args.next().as_deref() {
+                Some("show") => show_config = true,
+                _ => panic!("config supports: show"),
+            },
+            flag if flag.strip_prefix("--").is_some_and(Config::is_setting) => {
+                let value = args
+                    .next()
+                    .unwrap_or_else(|| panic!("{} requires a value", flag));
+                cli.push((flag[2..].to_string(), value));
+            }
+            _ => {}
+        }
+    }
+
+    // Layer the config file, CHT_* variables and then the flags over the
+    // defaults, and report every bad value at once.
+    let mut config = Config::new();
+    let config_path = config_path.or_else(|| std::env::var(config::CONFIG_ENV).ok());
+    match config_path.as_deref() {
+        Some(path) => config.load_file(Path::new(path)),
+        None if Path::new(config::DEFAULT_CONFIG_FILE).exists() => {
+            config.load_file(Path::new(config::DEFAULT_CONFIG_FILE))
+        }
+        None => {}
+    }
+    config.load_env(
+        std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }),
+    );
+    for (key, value) in cli {
+        config.set(&key, &value, Source::Cli);
+    }
+
+    // Apart from slow-op-ms every setting has a default, so None means it did
+    // not parse; the error is reported below.
+    options.log_path = config.get("log-path").unwrap().to_string();
+    options.command_file = config.get("commands").unwrap().to_string();
+    options.scheduler = config
+        .parse("scheduler", SchedulerKind::parse)
+        .unwrap_or_default();
+    options.hash_width = config
+        .parse("hash-width", HashWidth::parse)
+        .unwrap_or_default();
+    options.buckets = config.parse("buckets", |s| s.parse().ok().filter(|&n| n > 0));
+    options.stripes = config.parse("stripes", |s| s.parse().ok().filter(|&n| n > 0));
+    options.max_load_factor = config
+        .parse("max-load-factor", |s| match s {
+            "off" => Some(None),
+            _ => s.parse().ok().filter(|&f: &f64| f > 0.0).map(Some),
+        })
+        .flatten();
+    options.print_order = config
+        .parse("print-order", IterationOrder::parse)
+        .unwrap_or_default();
+    options.on_conflict = config
+        .parse("on-conflict", parse_conflict_policy)
+        .unwrap_or_default();
+    options.undo_depth = config.parse("undo-depth", |s| s.parse().ok());
+    options.slow_op_ms = config.parse("slow-op-ms", |s| s.parse().ok());
+    options.read_lease_ms = config.parse("read-lease-ms", |s| s.parse().ok());
+    options.read_lease_policy = config
+        .parse("read-lease-policy", LeasePolicy::parse)
+        .unwrap_or_default();
+    options.memory_limit = config.parse("memory-limit", memory::parse_bytes);
+    options.memory_policy = config
+        .parse("memory-policy", MemoryPolicy::parse)
+        .unwrap_or_default();
+    options.ingest = config.get("ingest").map(str::to_string);
+    options.ingest_workers = config.parse("ingest-workers", |s| s.parse().ok().filter(|&n| n > 0));
+    options.lookups = config.get("lookups").map(str::to_string);
+
+    if !config.errors().is_empty() {
+        for error in config.errors() {
+            eprintln!("Invalid configuration: {}", error);
         }
+        std::process::exit(1);
+    }
+    if show_config {
+        println!("{}", config);
+        std::process::exit(0);
     }
+    options
+}
+
+// Argument `index` of a command line (the priority is not one), if it is
+// there and parses.
+fn argument<T: FromStr>(parts: &[&str], index: usize) -> Option<T> {
+    parts[..parts.len() - 1].get(index)?.trim().parse().ok()
+}
+
+fn parse_conflict_policy(s: &str) -> Option<ConflictPolicy> {
+    match s {
+        "reject" => Some(ConflictPolicy::Reject),
+        "replace" => Some(ConflictPolicy::Replace),
+        "keep-max" => Some(ConflictPolicy::KeepMax),
+        // Adds the salaries; a sum in another currency keeps the old salary.
+        "sum" => Some(ConflictPolicy::Merge(|old, new| {
+            old.add(new, OverflowPolicy::Saturating).unwrap_or(old)
+        })),
+        _ => None,
+    }
+}
+
+fn parse_redaction(arg: Option<String>) -> Redaction {
+    arg.as_deref()
+        .and_then(Redaction::parse)
+        .expect("redaction must be one of: none, hash-keys, mask-values, drop")
 }
 
 fn main() {
-    let logger = Arc::new(ThreadLogger::new("hash.log"));
-    let hash_table = Arc::new(HashTable::new(Arc::clone(&logger)));
+    let options = parse_args();
+    // Before anything reads a key.
+    unicode::set_normalize_keys(options.normalize_keys);
+
+    // The same value the table would give a key with these bytes, for routing
+    // keys outside the program.
+    if let Some(input) = options.hash_input.as_deref() {
+        let hash = match input {
+            "-" => hash::hash_reader(options.hash_width, std::io::stdin().lock()),
+            path => std::fs::File::open(path)
+                .and_then(|file| hash::hash_reader(options.hash_width, file)),
+        };
+        match hash {
+            Ok(hash) => println!("{}  {}", hash, input),
+            Err(e) => {
+                eprintln!("Failed to hash {}: {}", input, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let file = File::open("commands.txt").expect("commands.txt not found");
-    let reader = BufReader::new(file);
+    if let Some((path, last)) = options.debug_replay.as_ref() {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path, e);
+            std::process::exit(2);
+        });
+        let mut replay = Replay::default();
+        for (step, line) in (1..).zip(text.lines()) {
+            let Some(event) = ChangeEvent::parse(line) else {
+                eprintln!("{}:{}: not an unredacted change: {}", path, step, line);
+                std::process::exit(2);
+            };
+            println!("Step {}: {}", step, line);
+            if let Some(problem) = replay.apply(&event.change) {
+                println!("  MISMATCH {}", problem);
+            }
+            for record in replay.records() {
+                println!("  {}", record);
+            }
+            if Some(step) == *last {
+                break;
+            }
+        }
+        return;
+    }
+
+    let mut logger = ThreadLogger::new(&options.log_path);
+    logger.set_redaction(options.log_redaction);
+    if let Some(lines) = options.log_high_watermark {
+        logger.set_high_watermark(lines);
+    }
+    if options.lock_analysis {
+        logger.enable_lock_trace();
+    }
+    if options.teach {
+        logger.enable_timeline();
+    }
+    let logger = Arc::new(logger);
 
+    if let Some((source, replica)) = options.anti_entropy.as_ref() {
+        let replica_table = load_records(&logger, &options, replica);
+        let repair =
+            merkle::anti_entropy(&load_records(&logger, &options, source), &replica_table, 0);
+        println!("{}", repair);
+        if repair.diverging > 0 {
+            let records: String = replica_table
+                .all_records(0)
+                .iter()
+                .map(|record| format!("{},{}\n", record.name, record.salary))
+                .collect();
+            if let Err(e) = fs::write(replica, records) {
+                eprintln!("Failed to write {}: {}", replica, e);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    if let Some((before, after)) = options.diff.as_ref() {
+        let before = load_records(&logger, &options, before);
+        let diff = before.diff(&load_records(&logger, &options, after), 0);
+
+        println!("{}", diff);
+        // Exiting skips destructors, so flush hash.log first.
+        drop(logger);
+        std::process::exit(if diff.is_empty() { 0 } else { 1 });
+    }
+    if let Some(cpus) = options.pin_cpus.clone() {
+        if let Err(e) = affinity::pin_workers(cpus) {
+            eprintln!("Failed to pin workers to {}", e);
+            std::process::exit(2);
+        }
+    }
+    let mut table = SalaryTable::with_logger(Arc::clone(&logger));
+    if let Some(path) = options.cdc_path.as_deref() {
+        let mut sink = FileSink::new(path).expect("Failed to create CDC file");
+        sink.set_redaction(options.cdc_redaction);
+        table.set_cdc_sink(Box::new(sink));
+    }
+    if options.unique_salary {
+        table.add_constraint(UniqueConstraint::salary());
+    }
+    add_triggers(&mut table, &options);
+    for (name, group, reducer) in options.views.iter() {
+        // Groups: everything together, by currency, or by the name's first letter.
+        match group.as_str() {
+            "all" => table.create_view(name, |_| "all".to_string(), *reducer),
+            "currency" => table.create_view(
+                name,
+                |record| record.salary.currency().as_str().to_string(),
+                *reducer,
+            ),
+            "initial" => table.create_view(
+                name,
+                |record| record.name.chars().take(1).collect(),
+                *reducer,
+            ),
+            _ => panic!("--view group must be all, currency or initial"),
+        }
+    }
+    if let Some(ms) = options.slow_op_ms {
+        table.set_slow_op_threshold(Duration::from_millis(ms));
+    }
+    for slo in options.slos.iter() {
+        table.add_slo(slo.clone());
+    }
+    if let Some(ms) = options.read_lease_ms {
+        table.set_read_lease(Duration::from_millis(ms), options.read_lease_policy);
+    }
+    if options.teach {
+        table.set_hold_stretch(TEACH_HOLD);
+    }
+    if options.track_access {
+        table.set_access_tracking();
+    }
+    if options.priority_locks {
+        table.set_priority_locking();
+    }
+    table.set_conflict_policy(options.on_conflict);
+    table.set_hash_width(options.hash_width);
+    if let Some(buckets) = options.buckets {
+        table.set_bucket_count(buckets);
+    }
+    if let Some(stripes) = options.stripes {
+        table.set_stripe_count(stripes);
+    }
+    table.set_config(HashTableConfig {
+        max_load_factor: options.max_load_factor,
+        ..HashTableConfig::default()
+    });
+    table.set_iteration_order(options.print_order);
+    if let Some(depth) = options.undo_depth {
+        table.set_undo_depth(depth);
+    }
+    if let Some(limit) = options.memory_limit {
+        table.set_memory_limit(limit, options.memory_policy);
+    }
+    let hash_table = Arc::new(table);
+
+    if let Some(path) = options.ingest.as_deref() {
+        let workers = options
+            .ingest_workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        match ingest::ingest(&hash_table, Path::new(path), workers) {
+            Ok(stats) => println!("{}", stats),
+            Err(e) => {
+                eprintln!("Failed to ingest {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let lines = match preprocess(Path::new(&options.command_file)) {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", options.command_file, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut registry = CommandRegistry::default();
+    register_commands(&mut registry);
     let mut commands = vec![];
 
-    for line in reader.lines() {
-        let line = line.expect("Failed to read line");
-        let parts: Vec<&str> = line.split(',').collect();
+    for line in lines.iter() {
+        let line = unicode::normalize_keys(line);
+        let mut parts: Vec<&str> = line.split(',').collect();
+        let (repeat, concurrent, tag) = take_annotations(&mut parts);
+        // explain,<command>,<priority>
+        let explain = parts[0].trim() == "explain";
+        if explain {
+            parts.remove(0);
+        }
 
         if parts.len() < 2 {
             println!("Invalid command format: {}", line);
@@ -59,127 +753,356 @@ fn main() {
         let priority: u32 = parts[parts.len() - 1].trim().parse().unwrap();
 
         let command = match command_str {
-            "insert" => Command::Insert {
-                name: parts[1].trim().to_string(),
-                salary: parts[2].trim().parse().unwrap(),
-            },
+            // insert,<name>,<salary>[,reject|replace|keep-max|sum],<priority>
+            "insert" => {
+                let policy = match parts.len() {
+                    4 => None,
+                    5 => match parse_conflict_policy(parts[3].trim()) {
+                        Some(policy) => Some(policy),
+                        None => {
+                            println!("Invalid command format: {}", line);
+                            continue;
+                        }
+                    },
+                    _ => {
+                        println!("Invalid command format: {}", line);
+                        continue;
+                    }
+                };
+                Command::Insert {
+                    name: parts[1].trim().to_string(),
+                    salary: parts[2].trim().parse().unwrap(),
+                    policy,
+                }
+            }
             "delete" => Command::Delete {
                 name: parts[1].trim().to_string(),
             },
+            "pop_front" => Command::Pop { end: End::Front },
+            "pop_back" => Command::Pop { end: End::Back },
             "update" => Command::Update {
                 name: parts[1].trim().to_string(),
                 salary: parts[2].trim().parse().unwrap(),
             },
+            // update_if,<name>,<expected>,<salary>,<priority>
+            "update_if" => match (parts.len(), argument(&parts, 2), argument(&parts, 3)) {
+                (5, Some(expected), Some(salary)) => Command::UpdateIf {
+                    name: parts[1].trim().to_string(),
+                    expected,
+                    salary,
+                },
+                _ => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            "upsert" => Command::Upsert {
+                name: parts[1].trim().to_string(),
+                salary: parts[2].trim().parse().unwrap(),
+            },
+            // adjust,<name>,<delta>[,checked|saturating|wrapping],<priority>
+            "adjust" => {
+                let policy = match parts.len() {
+                    4 => Some(OverflowPolicy::default()),
+                    5 => OverflowPolicy::parse(parts[3].trim()),
+                    _ => None,
+                };
+                let Some(policy) = policy else {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                };
+                Command::Adjust {
+                    name: parts[1].trim().to_string(),
+                    delta: parts[2].trim().parse().unwrap(),
+                    policy,
+                }
+            }
+            // raise,<pct>,<min>,<max>,<priority>
+            "raise" => match (
+                parts.len(),
+                money::parse_percent(parts[1]),
+                argument(&parts, 2),
+                argument(&parts, 3),
+            ) {
+                (5, Some(basis_points), Some(min), Some(max)) => Command::Raise {
+                    basis_points,
+                    min,
+                    max,
+                },
+                _ => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
             "search" => Command::Search {
                 name: parts[1].trim().to_string(),
             },
+            "inspect" => Command::Inspect {
+                name: parts[1].trim().to_string(),
+            },
             "print" => Command::Print,
+            "keys" => Command::Keys,
+            "minmax" => Command::MinMax,
+            // report,<bucket width>,<priority>
+            "report" => match parts[1].trim().parse::<Money>() {
+                Ok(width) if width.minor() > 0 => Command::Report { width },
+                _ => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            "undo" => Command::Undo,
+            "deleted" => Command::Deleted,
+            "memory" => Command::Memory,
+            // rehash,<32|64|128>,<priority>
+            "rehash" => match HashWidth::parse(parts[1].trim()) {
+                Some(width) => Command::Rehash { width },
+                None => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            // reload,<path>,<priority>
+            "reload" => Command::Reload {
+                path: parts[1].trim().to_string(),
+            },
+            #[cfg(feature = "ordered")]
+            "range" => Command::Range {
+                from: parts[1].trim().to_string(),
+                to: parts[2].trim().to_string(),
+            },
+            // query,<expr>,<priority>; the expression may itself contain commas.
+            "query" => match Query::parse(&parts[1..parts.len() - 1].join(",")) {
+                Ok(query) => Command::Query { query },
+                Err(e) => {
+                    println!("Invalid query ({}): {}", e, line);
+                    continue;
+                }
+            },
+            "view" => Command::View {
+                name: parts[1].trim().to_string(),
+            },
+            // sleep,<ms>,<priority>
+            "sleep" => match argument(&parts, 1) {
+                Some(ms) => Command::Sleep { ms },
+                None => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            "barrier" => Command::Barrier,
+            "assert_exists" => Command::AssertExists {
+                name: parts[1].trim().to_string(),
+            },
+            "assert_salary" => match argument(&parts, 2) {
+                Some(salary) => Command::AssertSalary {
+                    name: parts[1].trim().to_string(),
+                    salary,
+                },
+                None => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            // assert_count,<count>,<priority>
+            "assert_count" => match argument(&parts, 1) {
+                Some(count) => Command::AssertCount { count },
+                None => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            "ifexists" | "ifabsent" => match parse_conditional(command_str, &parts) {
+                Some(command) => command,
+                None => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
+            // similar,<max edit distance>,<priority>
+            "similar" => match argument(&parts, 1) {
+                Some(max_distance) => Command::Similar { max_distance },
+                None => {
+                    println!("Invalid command format: {}", line);
+                    continue;
+                }
+            },
             "threads" => {
                 // "threads" command is no longer used.
                 continue;
             }
-            _ => {
-                println!("Unknown command: {}", command_str);
+            _ => match registry.get(command_str) {
+                Some(command) => Command::Custom {
+                    args: parts[1..parts.len() - 1]
+                        .iter()
+                        .map(|arg| arg.trim().to_string())
+                        .collect(),
+                    command,
+                },
+                None => {
+                    println!("Unknown command: {}", command_str);
+                    continue;
+                }
+            },
+        };
+        if (repeat != 1 || concurrent)
+            && matches!(command, Command::Sleep { .. } | Command::Barrier)
+        {
+            println!("Invalid command format: {}", line);
+            continue;
+        }
+        let command = if explain {
+            if matches!(command, Command::Sleep { .. } | Command::Barrier) {
+                println!("Invalid command format: {}", line);
                 continue;
             }
+            Command::Explain {
+                text: parts[..parts.len() - 1].join(","),
+                command: Box::new(command),
+            }
+        } else {
+            command
         };
-        commands.push(CommandWithPriority { command, priority });
+        if options.validate_keys {
+            if let Err(e) = command.key().map_or(Ok(()), keys::validate) {
+                println!("Invalid key ({}): {:?}", e, line);
+                continue;
+            }
+        }
+
+        // Correlation IDs follow file order, so they stay stable across runs.
+        let correlation_id = commands.len() as u64 + 1;
+        commands.push(CommandWithPriority {
+            command,
+            priority,
+            correlation_id,
+            repeat,
+            concurrent,
+            tag,
+        });
     }
 
-    // Sort commands by priority
-    commands.sort_by_key(|k| k.priority);
+    if options.log_header {
+        logger.log_header(&RunHeader {
+            backend: hash_table.backend(),
+            buckets: hash_table.bucket_count(),
+            stripes: hash_table.stripe_count(),
+            hash: hash_table.hash_width().name(),
+            hash_seed: None,
+            threads: commands.len(),
+            cpus: affinity::pinned().map(<[usize]>::to_vec),
+            command_file: options.command_file.clone(),
+            command_file_hash: SalaryTable::jenkins_one_at_a_time_hash(lines.join("\n").as_bytes()),
+        });
+    }
 
-    let turn_manager = Arc::new(TurnManager::new(0));
+    // Order commands by priority; ties are broken by --ties.
+    let turns = assign_turns(commands, |c| c.priority, options.ties);
+    let slot_count = turns.last().map_or(0, |(slot, _)| slot + 1);
+    let mut slot_sizes = vec![0; slot_count];
+    for (slot, _) in turns.iter() {
+        slot_sizes[*slot] += 1;
+    }
+    // Number of commands in all earlier slots, which a barrier waits for.
+    let slot_starts: Vec<usize> = slot_sizes
+        .iter()
+        .scan(0, |total, size| {
+            let start = *total;
+            *total += size;
+            Some(start)
+        })
+        .collect();
+
+    let scheduler: Arc<dyn Scheduler> = options.scheduler.build(slot_sizes).into();
+    let failed_assertions = Arc::new(AtomicUsize::new(0));
+    let output = OutputCollector::new(options.output_order);
     let mut handles = vec![];
 
-    for CommandWithPriority { command, priority } in commands.into_iter() {
+    // `seq` is the submission order: the order commands go to the scheduler.
+    for (
+        seq,
+        (
+            slot,
+            CommandWithPriority {
+                command,
+                priority,
+                correlation_id,
+                repeat,
+                concurrent,
+                tag,
+            },
+        ),
+    ) in turns.into_iter().enumerate()
+    {
+        let outcomes = output.sender();
+        let earlier_commands = slot_starts[slot];
         let table = Arc::clone(&hash_table);
-        let turn_manager_clone = Arc::clone(&turn_manager);
+        let scheduler = Arc::clone(&scheduler);
 
         let logger = Arc::clone(&logger);
+        let failed_assertions = Arc::clone(&failed_assertions);
+        let correlation_ids = options.correlation_ids;
         let handle = thread::spawn(move || {
-            logger.log_id(
-                priority as u32,
-                LogMessage::Custom("WAITING FOR MY TURN".to_string()),
-            );
-
-            let mut turn = turn_manager_clone.current_turn.lock().unwrap();
-
-            while *turn != priority as u32 {
-                turn = turn_manager_clone.condvar.wait(turn).unwrap();
+            affinity::pin_worker(seq);
+            logger.register_thread(priority);
+            if correlation_ids {
+                logger::set_correlation_id(Some(correlation_id));
             }
+            logger::set_tag(tag.as_deref());
+            logger.log(LogMessage::Custom("WAITING FOR MY TURN".to_string()));
 
-            *turn += 1;
-
-            turn_manager_clone.condvar.notify_all();
-
-            // Release the lock before doing the work
-            drop(turn);
-
-            logger.log_id(
-                priority as u32,
-                LogMessage::Custom("AWAKENED FOR WORK".to_string()),
+            let requested = Instant::now();
+            scheduler.wait_for_turn(slot);
+            logger.record_span(
+                priority.to_string(),
+                TURN_LOCK,
+                LockType::Write,
+                requested,
+                Instant::now(),
             );
+            logger.record_lock_event(TURN_LOCK, LockType::Write, LockAction::Acquire);
+            logger.record_lock_event(TURN_LOCK, LockType::Write, LockAction::Release);
 
+            // Sleep and barrier wait while holding the turn, so later commands stay
+            // parked until they start.
             match command {
-                Command::Insert { name, salary } => {
-                    let result = table.insert(&name, salary, priority);
-                    match result {
-                        InsertResult::Success { record } => {
-                            println!("Inserted {}", record);
-                        }
-                        InsertResult::Duplicate { hash } => {
-                            println!("Duplicate entry for {},{}", hash, name);
-                        }
-                    }
+                Command::Sleep { ms } => {
+                    logger.log(LogMessage::Custom(format!("SLEEP,{}", ms)));
+                    thread::sleep(Duration::from_millis(ms));
                 }
-                Command::Delete { name } => {
-                    let result = table.delete(&name, priority);
-                    match result {
-                        DeleteResult::Success { record } => {
-                            println!("Deleted record for {}", record);
-                        }
-                        DeleteResult::NotFound { .. } => {
-                            println!("{} not found", name);
-                        }
-                    }
-                }
-                Command::Update { name, salary } => {
-                    let result = table.update_salary(&name, salary, priority);
-                    match result {
-                        UpdateResult::Success {
-                            old_record,
-                            new_record,
-                        } => {
-                            println!(
-                                "Updated record {} from {} to {}",
-                                old_record.hash, old_record, new_record
-                            );
-                        }
-                        UpdateResult::NotFound { hash } => {
-                            println!("Update failed. Entry {} not found.", hash);
-                        }
-                    }
+                Command::Barrier => {
+                    logger.log(LogMessage::Custom("BARRIER".to_string()));
+                    scheduler.wait_for_completed(earlier_commands);
                 }
-                Command::Search { name } => {
-                    let result = table.search(&name, priority);
-                    match result {
-                        SearchResult::Found { record } => {
-                            println!("Found: {}", record);
-                        }
-                        SearchResult::NotFound { name } => {
-                            println!("{} not found.", name);
-                        }
+                _ => {}
+            }
+            scheduler.start(slot);
+
+            logger.log(LogMessage::Custom("AWAKENED FOR WORK".to_string()));
+
+            if concurrent && repeat > 1 {
+                execute_concurrently(
+                    &table,
+                    &command,
+                    priority,
+                    repeat,
+                    &failed_assertions,
+                    &outcomes,
+                    seq,
+                );
+            } else {
+                for _ in 0..repeat {
+                    if let Some(outcome) =
+                        execute(&table, command.clone(), priority, &failed_assertions)
+                    {
+                        outcomes.send(seq, outcome);
                     }
                 }
-                Command::Print => {
-                    println!("Current Database:");
-                    let result = table.get_all_records(priority);
-                    result.iter().for_each(|record| {
-                        println!("{}", record);
-                    });
-                }
             }
+            outcomes.done(seq);
+
+            scheduler.finish(slot);
+            logger.unregister_thread();
         });
         handles.push(handle);
     }
@@ -187,16 +1110,803 @@ fn main() {
     for handle in handles {
         handle.join().unwrap();
     }
+    output.finish();
 
     // Final compulsory stdout print. This prints with thread ID 0,
     // since all threads have completed and we're calling this from the main thread.
     println!("Final Table:");
-    hash_table.get_all_records(0).iter().for_each(|record| {
+    hash_table.all_records(0).iter().for_each(|record| {
         println!("{}", record);
     });
 
+    if let Some(path) = options.lookups.as_deref() {
+        let keys = match fs::read_to_string(path) {
+            Ok(keys) => keys,
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        let keys = unicode::normalize_keys(&keys);
+        let keys: Vec<&str> = keys
+            .lines()
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .collect();
+        let frozen = hash_table.freeze(0);
+        let outcomes = serve_lookups(&frozen, &keys);
+        let found = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, CommandOutcome::Found { .. }))
+            .count();
+        println!(
+            "Lookups ({} of {} found among {} frozen records):",
+            found,
+            keys.len(),
+            frozen.records().len()
+        );
+
+        for outcome in outcomes {
+            println!("{}", outcome);
+        }
+    }
+
+    if options.stats {
+        println!("Throughput:");
+        for seconds in [1, 10, 60] {
+            println!("{}", hash_table.stats_window(Duration::from_secs(seconds)));
+        }
+        let by_tag = hash_table.tag_stats();
+        if !by_tag.is_empty() {
+            println!("By tag:");
+            for stats in by_tag {
+                println!("{}", stats);
+            }
+        }
+        let slos = hash_table.slo_status();
+        if !slos.is_empty() {
+            println!("SLOs:");
+            for status in slos {
+                println!("{}", status);
+            }
+        }
+        println!("Hash quality: {}", hash_table.hash_quality());
+        println!("Memory: {}", hash_table.memory_usage());
+        if allocs::ENABLED {
+            println!("Allocations:\n{}", hash_table.allocation_stats());
+        }
+    }
+
+    if options.lock_analysis {
+        println!("{}", logger.analyze_lock_order());
+    }
+    if options.tune_stripes {
+        println!("Stripe advice: {}", hash_table.stripe_advice());
+    }
+
+    if let Some(timeline) = logger.timeline() {
+        println!("{}", timeline.render(TIMELINE_COLUMNS));
+    }
+
     // This is also called AFTER the thread log, so it won't include the final read lock
     // acquisition - the original expected output doesn't.
     // Final log summary of table to hash.log along with lock statistics.
     hash_table.log_summary();
+
+    let failed = failed_assertions.load(Ordering::SeqCst);
+    if failed > 0 {
+        eprintln!("{} assertion(s) failed", failed);
+        // Exiting skips destructors, so flush hash.log first.
+        drop(hash_table);
+        drop(logger);
+        std::process::exit(1);
+    }
+}
+
+// A fresh table holding a record file, bulk-loaded in one pass. Exits if the
+// file cannot be read.
+fn load_records(logger: &Arc<ThreadLogger>, options: &Options, path: &str) -> SalaryTable {
+    let workers = options
+        .ingest_workers
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let mut table = SalaryTable::with_logger(logger.clone());
+    table.set_hash_width(options.hash_width);
+    if let Err(e) = ingest::load(&table, Path::new(path), workers) {
+        eprintln!("Failed to load {}: {}", path, e);
+        std::process::exit(2);
+    }
+    table
+}
+
+// Looks up every key in `frozen`, split across all cores. Nothing is locked,
+// so the threads never wait on each other. Outcomes are in key order.
+fn serve_lookups(frozen: &FrozenTable, keys: &[&str]) -> Vec<CommandOutcome> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = keys.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = keys
+            .chunks(chunk)
+            .map(|keys| {
+                scope.spawn(move || {
+                    keys.iter()
+                        .map(|key| match frozen.get(key) {
+                            Some(record) => CommandOutcome::Found {
+                                record: record.clone(),
+                            },
+                            None => CommandOutcome::SearchNotFound {
+                                name: key.to_string(),
+                            },
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+// Strips trailing `xN` / `concurrent` / `tag=<name>` annotations (just before
+// the priority), e.g. `search,Link,x1000,concurrent,tag=api,5`. Returns
+// (repeat, concurrent, tag).
+fn take_annotations(parts: &mut Vec<&str>) -> (usize, bool, Option<String>) {
+    let mut repeat = 1;
+    let mut concurrent = false;
+    let mut tag = None;
+
+    while parts.len() > 2 {
+        let annotation = parts[parts.len() - 2].trim();
+        if annotation == "concurrent" {
+            concurrent = true;
+        } else if let Some(name) = annotation.strip_prefix("tag=").filter(|n| !n.is_empty()) {
+            tag = Some(name.to_string());
+        } else if let Some(n) = annotation
+            .strip_prefix('x')
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            repeat = n;
+        } else {
+            break;
+        }
+        parts.remove(parts.len() - 2);
+    }
+
+    (repeat, concurrent, tag)
+}
+
+fn execute(
+    table: &SalaryTable,
+    command: Command,
+    priority: u32,
+    failed_assertions: &AtomicUsize,
+) -> Option<CommandOutcome> {
+    let outcome = match command {
+        Command::Insert {
+            name,
+            salary,
+            policy,
+        } => match policy.map_or_else(
+            || table.insert(&name, salary, priority),
+            |policy| table.insert_with(&name, salary, policy, priority),
+        ) {
+            InsertResult::Success { record } => CommandOutcome::Inserted { record },
+            InsertResult::Duplicate { hash } => CommandOutcome::Duplicate { hash, name },
+            InsertResult::Merged {
+                old_record,
+                new_record,
+            } => CommandOutcome::Merged {
+                old_record,
+                new_record,
+            },
+            InsertResult::ConstraintViolation { hash, constraint } => {
+                CommandOutcome::ConstraintViolation { hash, constraint }
+            }
+            InsertResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+            InsertResult::OutOfMemoryBudget { hash } => CommandOutcome::OutOfMemoryBudget { hash },
+        },
+        Command::Delete { name } => match table.delete(&name, priority) {
+            DeleteResult::Success { record } => CommandOutcome::Deleted { record },
+            DeleteResult::NotFound { .. } => CommandOutcome::DeleteNotFound { name },
+            DeleteResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+        },
+        Command::Pop { end } => match table.pop(end, priority) {
+            Some(record) => CommandOutcome::Deleted { record },
+            None => CommandOutcome::PopEmpty,
+        },
+        Command::Update { name, salary } => match table.update_salary(&name, salary, priority) {
+            UpdateResult::Success {
+                old_record,
+                new_record,
+            } => CommandOutcome::Updated {
+                old_record,
+                new_record,
+            },
+            UpdateResult::NotFound { hash } => CommandOutcome::UpdateNotFound { hash },
+            UpdateResult::Conflict { .. } => unreachable!("only update_salary_if conflicts"),
+            UpdateResult::ConstraintViolation { hash, constraint } => {
+                CommandOutcome::ConstraintViolation { hash, constraint }
+            }
+            UpdateResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+        },
+        Command::UpdateIf {
+            name,
+            expected,
+            salary,
+        } => match table.update_salary_if(&name, expected, salary, priority) {
+            UpdateResult::Success {
+                old_record,
+                new_record,
+            } => CommandOutcome::Updated {
+                old_record,
+                new_record,
+            },
+            UpdateResult::NotFound { hash } => CommandOutcome::UpdateNotFound { hash },
+            UpdateResult::Conflict { actual } => {
+                CommandOutcome::UpdateConflict { expected, actual }
+            }
+            UpdateResult::ConstraintViolation { hash, constraint } => {
+                CommandOutcome::ConstraintViolation { hash, constraint }
+            }
+            UpdateResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+        },
+        Command::Upsert { name, salary } => match table.upsert(&name, salary, priority) {
+            UpsertResult::Inserted { record } => CommandOutcome::Inserted { record },
+            UpsertResult::Updated {
+                old_record,
+                new_record,
+            } => CommandOutcome::Updated {
+                old_record,
+                new_record,
+            },
+            UpsertResult::ConstraintViolation { hash, constraint } => {
+                CommandOutcome::ConstraintViolation { hash, constraint }
+            }
+            UpsertResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+            UpsertResult::OutOfMemoryBudget { hash } => CommandOutcome::OutOfMemoryBudget { hash },
+        },
+        Command::Adjust {
+            name,
+            delta,
+            policy,
+        } => match table.adjust_salary(&name, delta, policy, priority) {
+            AdjustResult::Success {
+                old_record,
+                new_record,
+            } => CommandOutcome::Adjusted {
+                old_record,
+                new_record,
+            },
+            AdjustResult::NotFound { hash } => CommandOutcome::AdjustNotFound { hash },
+            AdjustResult::Rejected { hash, error } => {
+                CommandOutcome::AdjustRejected { hash, error }
+            }
+            AdjustResult::ConstraintViolation { hash, constraint } => {
+                CommandOutcome::ConstraintViolation { hash, constraint }
+            }
+            AdjustResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+        },
+        Command::Raise {
+            basis_points,
+            min,
+            max,
+        } => CommandOutcome::Raised {
+            count: table.update_where(
+                |record| min <= record.salary && record.salary <= max,
+                // A raise that would overflow leaves the salary as it was.
+                |record| {
+                    record
+                        .salary
+                        .scale_percent(basis_points)
+                        .unwrap_or(record.salary)
+                },
+                priority,
+            ),
+        },
+        Command::Search { name } => match table.search(&name, priority) {
+            SearchResult::Found { record } => CommandOutcome::Found { record },
+            SearchResult::NotFound { name } => CommandOutcome::SearchNotFound { name },
+        },
+        Command::Inspect { name } => match table.inspect(&name, priority) {
+            Some((record, meta)) => CommandOutcome::Inspected { record, meta },
+            None => CommandOutcome::SearchNotFound { name },
+        },
+        Command::Print => CommandOutcome::Table {
+            records: table.get_all_records(priority),
+        },
+        Command::Keys => CommandOutcome::Keys {
+            keys: table.keys_sorted(priority),
+        },
+        Command::Undo => match table.undo_last_delete(priority) {
+            UndoResult::Restored { record } => CommandOutcome::Restored { record },
+            UndoResult::Empty => CommandOutcome::NothingToUndo,
+            UndoResult::Failed { record, reason } => CommandOutcome::UndoFailed { record, reason },
+        },
+        Command::Deleted => CommandOutcome::RecentlyDeleted {
+            records: table.recently_deleted(),
+        },
+        Command::Memory => CommandOutcome::Memory {
+            usage: table.memory_usage(),
+        },
+        Command::Rehash { width } => CommandOutcome::Rehashed {
+            count: table.rehash_with(width, priority),
+            function: width.name(),
+        },
+        Command::Reload { path } => {
+            let fresh = table.sibling();
+            let workers = thread::available_parallelism().map_or(1, |n| n.get());
+            match ingest::load(&fresh, Path::new(&path), workers) {
+                Ok(stats) => {
+                    table.swap(&fresh, priority);
+                    CommandOutcome::Reloaded {
+                        path,
+                        stats,
+                        replaced: fresh.salary_count(),
+                    }
+                }
+                Err(e) => CommandOutcome::ReloadFailed {
+                    path,
+                    error: e.to_string(),
+                },
+            }
+        }
+        Command::Custom { args, command } => {
+            return (command.handler)(
+                table,
+                &CommandContext {
+                    args: &args,
+                    priority,
+                },
+            )
+        }
+        Command::MinMax => CommandOutcome::MinMax {
+            count: table.salary_count(),
+            min: table.min_salary(),
+            max: table.max_salary(),
+        },
+        Command::Report { width } => CommandOutcome::Report {
+            histogram: table.histogram_by_salary(width, priority),
+        },
+        Command::Similar { max_distance } => CommandOutcome::Similar {
+            keys: table.find_similar_keys(max_distance, priority),
+        },
+
+        #[cfg(feature = "ordered")]
+        Command::Range { from, to } => CommandOutcome::Query {
+            records: table.range(&from, &to, priority),
+        },
+
+        Command::Query { query } => CommandOutcome::Query {
+            records: table.select_where(|record| query.matches(record), priority),
+        },
+        Command::View { name } => CommandOutcome::View {
+            rows: table
+                .view(&name)
+                .map(|rows| rows.iter().map(|row| row.to_string()).collect()),
+            name,
+        },
+        // Handled before the turn was passed.
+        Command::Sleep { .. } | Command::Barrier => return None,
+        Command::AssertExists { name } => {
+            if let SearchResult::NotFound { .. } = table.search(&name, priority) {
+                assertion_failed(
+                    failed_assertions,
+                    priority,
+                    format!("expected {} to exist", name),
+                );
+            }
+            return None;
+        }
+        Command::AssertSalary { name, salary } => {
+            match table.search(&name, priority) {
+                SearchResult::Found { record } if record.salary == salary => {}
+                SearchResult::Found { record } => assertion_failed(
+                    failed_assertions,
+                    priority,
+                    format!(
+                        "expected {} to have salary {}, found {}",
+                        name, salary, record.salary
+                    ),
+                ),
+                SearchResult::NotFound { .. } => assertion_failed(
+                    failed_assertions,
+                    priority,
+                    format!(
+                        "expected {} to have salary {}, but it does not exist",
+                        name, salary
+                    ),
+                ),
+            }
+            return None;
+        }
+        Command::If { name, conditional } => match table.apply_if(&name, conditional, priority) {
+            ConditionalResult::Inserted { record } => CommandOutcome::Inserted { record },
+            ConditionalResult::Updated {
+                old_record,
+                new_record,
+            } => CommandOutcome::Updated {
+                old_record,
+                new_record,
+            },
+            ConditionalResult::Deleted { record } => CommandOutcome::Deleted { record },
+            ConditionalResult::Skipped { hash } => CommandOutcome::Skipped { hash, name },
+            ConditionalResult::ConstraintViolation { hash, constraint } => {
+                CommandOutcome::ConstraintViolation { hash, constraint }
+            }
+            ConditionalResult::Vetoed { hash, reason } => CommandOutcome::Vetoed { hash, reason },
+            ConditionalResult::OutOfMemoryBudget { hash } => {
+                CommandOutcome::OutOfMemoryBudget { hash }
+            }
+        },
+        Command::Explain { text, command } => CommandOutcome::Explained {
+            explanation: table.explain(command.key(), command.lock(), priority),
+            command: text,
+        },
+        Command::AssertCount { count } => {
+            let actual = table.count(priority);
+            if actual != count {
+                assertion_failed(
+                    failed_assertions,
+                    priority,
+                    format!("expected {} records, found {}", count, actual),
+                );
+            }
+            return None;
+        }
+    };
+    Some(outcome)
+}
+
+// Runs `repeat` copies of the command, spread over up to one worker per CPU
+// (per --pin-cpus CPU when pinned).
+fn execute_concurrently(
+    table: &SalaryTable,
+    command: &Command,
+    priority: u32,
+    repeat: usize,
+    failed_assertions: &AtomicUsize,
+    outcomes: &OutcomeSender,
+    seq: usize,
+) {
+    let workers = match affinity::pinned() {
+        Some(cpus) => cpus.len(),
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    }
+    .min(repeat);
+
+    let correlation_id = logger::correlation_id();
+
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            // Worker w runs iterations w, w + workers, w + 2 * workers, ...
+            let iterations = (repeat - worker).div_ceil(workers);
+            scope.spawn(move || {
+                affinity::pin_worker(worker);
+                table
+                    .logger()
+                    .register_thread(format!("{}.{}", priority, worker));
+                logger::set_correlation_id(correlation_id);
+                for _ in 0..iterations {
+                    if let Some(outcome) =
+                        execute(table, command.clone(), priority, failed_assertions)
+                    {
+                        outcomes.send(seq, outcome);
+                    }
+                }
+                table.logger().unregister_thread();
+            });
+        }
+    });
+}
+
+// ifexists,<name>,then=update,<name>,<salary>,<priority>
+// ifexists,<name>,then=delete,<name>,<priority>
+// ifabsent,<name>,then=insert,<name>,<salary>,<priority>
+// The inner command must target the same name so it can share the check's lock.
+fn parse_conditional(command_str: &str, parts: &[&str]) -> Option<Command> {
+    let name = parts.get(1)?.trim();
+    let inner = parts.get(2)?.trim().strip_prefix("then=")?;
+    let args = &parts[3..parts.len() - 1];
+
+    if args.first()?.trim() != name {
+        return None;
+    }
+    let salary = || args.get(1).and_then(|s| s.trim().parse().ok());
+
+    let conditional = match (command_str, inner) {
+        ("ifabsent", "insert") => Conditional::InsertIfAbsent { salary: salary()? },
+        ("ifexists", "update") => Conditional::UpdateIfExists { salary: salary()? },
+        ("ifexists", "delete") => Conditional::DeleteIfExists,
+        _ => return None,
+    };
+
+    Some(Command::If {
+        name: name.to_string(),
+        conditional,
+    })
+}
+
+// --protect vetoes updates and deletes of the named records. --headcount keeps
+// a record whose salary is the number of other records in the table.
+// Commands built on the table's public API rather than the parser.
+fn register_commands(registry: &mut CommandRegistry) {
+    // total[,<currency>...],<priority>: the sum of all salaries in each
+    // currency, or only in the given ones.
+    registry.register_command("total", Some(LockType::Read), |table, context| {
+        let wanted = |record: &HashRecord| {
+            let currency = record.salary.currency();
+            context.args.is_empty() || context.args.iter().any(|c| c == currency.as_str())
+        };
+        let records = table.select_where(wanted, context.priority);
+        Some(CommandOutcome::Custom {
+            text: describe_totals(records.iter()),
+        })
+    });
+
+    // whatif,<pct>,<min>,<max>,<priority>: the totals `raise` with the same
+    // arguments would leave, worked out on a fork so the table is untouched.
+    registry.register_command("whatif", Some(LockType::Read), |table, context| {
+        let parsed = match context.args {
+            [pct, min, max] => money::parse_percent(pct)
+                .zip(min.parse::<Money>().ok())
+                .zip(max.parse::<Money>().ok()),
+            _ => None,
+        };
+        let Some(((basis_points, min), max)) = parsed else {
+            return Some(CommandOutcome::Custom {
+                text: "Invalid whatif, expected <pct>,<min>,<max>".to_string(),
+            });
+        };
+        let mut fork = table.fork(context.priority);
+        let raised: Vec<(String, Money)> = fork
+            .records()
+            .filter(|record| min <= record.salary && record.salary <= max)
+            .filter_map(|record| {
+                let salary = record.salary.scale_percent(basis_points).ok()?;
+                Some((record.name.clone(), salary))
+            })
+            .collect();
+        for (name, salary) in raised {
+            fork.set_salary(&name, salary);
+        }
+        let text = format!(
+            "What if {} records were raised {}%: {}",
+            fork.copied(),
+            context.args[0],
+            describe_totals(fork.records())
+        );
+        Some(CommandOutcome::Custom { text })
+    });
+}
+
+// The sum of the salaries in each currency, as `total` prints it.
+fn describe_totals<'a>(records: impl Iterator<Item = &'a HashRecord>) -> String {
+    let mut totals: Vec<Money> = Vec::new();
+    for record in records {
+        match totals
+            .iter_mut()
+            .find(|total| total.currency() == record.salary.currency())
+        {
+            Some(total) => {
+                *total = total
+                    .add(record.salary, OverflowPolicy::Saturating)
+                    .unwrap_or(*total)
+            }
+            None => totals.push(record.salary),
+        }
+    }
+    match totals.is_empty() {
+        true => "No salaries.".to_string(),
+        false => format!(
+            "Total salaries: {}",
+            totals
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(" | ")
+        ),
+    }
+}
+
+fn add_triggers(table: &mut SalaryTable, options: &Options) {
+    let triggers = table.triggers_mut();
+
+    if !options.protected.is_empty() {
+        let protected: Vec<String> = options
+            .protected
+            .iter()
+            .map(|name| unicode::normalize_keys(name).into_owned())
+            .collect();
+        let protected = Arc::new(protected);
+        let veto = move |event: &trigger::TriggerEvent| {
+            if protected.iter().any(|name| name == event.name) {
+                TriggerAction::Veto {
+                    reason: "record is protected".to_string(),
+                }
+            } else {
+                TriggerAction::Continue
+            }
+        };
+        triggers.on_update(veto.clone());
+        triggers.on_delete(veto);
+    }
+
+    if let Some(counter) = options.headcount.as_deref() {
+        let counter = unicode::normalize_keys(counter).into_owned();
+        let one: Money = "1".parse().unwrap();
+        let minus_one: Money = "-1".parse().unwrap();
+        let zero: Money = "0".parse().unwrap();
+
+        let name = counter.clone();
+        triggers.on_insert(move |event| {
+            if *event.name == name {
+                return TriggerAction::Continue;
+            }
+            TriggerAction::Then(vec![
+                // Creates the counter the first time; a duplicate is ignored.
+                FollowUp::Insert {
+                    name: name.clone(),
+                    salary: zero,
+                },
+                FollowUp::Adjust {
+                    name: name.clone(),
+                    delta: one,
+                },
+            ])
+        });
+        triggers.on_delete(move |event| {
+            if *event.name == counter {
+                return TriggerAction::Continue;
+            }
+            TriggerAction::Then(vec![FollowUp::Adjust {
+                name: counter.clone(),
+                delta: minus_one,
+            }])
+        });
+    }
+}
+
+fn assertion_failed(failed: &AtomicUsize, priority: u32, message: String) {
+    failed.fetch_add(1, Ordering::SeqCst);
+    eprintln!("Assertion failed (priority {}): {}", priority, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute, execute_concurrently, parse_conflict_policy, take_annotations, Command};
+    use crate::hash_table::{InsertResult, SalaryTable, SearchResult};
+    use crate::money::OverflowPolicy;
+    use crate::output::{OutputCollector, OutputOrder};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_assertions() {
+        let table = SalaryTable::new();
+        let money = |s: &str| s.parse().unwrap();
+        table.insert("a", money("1"), 0);
+        table.insert("b", money("2"), 0);
+
+        let failed = AtomicUsize::new(0);
+        let passing = [
+            Command::AssertCount { count: 2 },
+            Command::AssertExists {
+                name: "a".to_string(),
+            },
+            Command::AssertSalary {
+                name: "b".to_string(),
+                salary: money("2"),
+            },
+        ];
+        for command in passing {
+            assert!(execute(&table, command, 0, &failed).is_none());
+        }
+        assert_eq!(0, failed.load(Ordering::SeqCst));
+
+        let failing = [
+            Command::AssertCount { count: 3 },
+            Command::AssertExists {
+                name: "c".to_string(),
+            },
+            Command::AssertSalary {
+                name: "b".to_string(),
+                salary: money("3"),
+            },
+        ];
+        for command in failing {
+            assert!(execute(&table, command, 0, &failed).is_none());
+        }
+        assert_eq!(3, failed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_take_annotations() {
+        let mut parts = vec!["search", "Link", "x1000", "concurrent", "tag=api", "5"];
+        assert_eq!(
+            (1000, true, Some("api".to_string())),
+            take_annotations(&mut parts)
+        );
+        assert_eq!(vec!["search", "Link", "5"], parts);
+
+        // Only annotations just before the priority are taken.
+        let mut parts = vec!["insert", "x2", "10", "5"];
+        assert_eq!((1, false, None), take_annotations(&mut parts));
+        assert_eq!(vec!["insert", "x2", "10", "5"], parts);
+    }
+
+    #[test]
+    fn test_execute_concurrently() {
+        let table = SalaryTable::new();
+        let failed = AtomicUsize::new(0);
+        let collector = OutputCollector::with_writer(OutputOrder::Completion, std::io::sink());
+        let outcomes = collector.sender();
+        let money = |s: &str| s.parse().unwrap();
+
+        // x50 of one insert leaves one record; x100 of +1 adds 100.
+        let insert = Command::Insert {
+            name: "a".to_string(),
+            salary: money("0"),
+            policy: None,
+        };
+        execute_concurrently(&table, &insert, 0, 50, &failed, &outcomes, 0);
+        let adjust = Command::Adjust {
+            name: "a".to_string(),
+            delta: money("1"),
+            policy: OverflowPolicy::Checked,
+        };
+        execute_concurrently(&table, &adjust, 0, 100, &failed, &outcomes, 1);
+        drop(outcomes);
+        collector.finish();
+
+        assert_eq!(1, table.count(0));
+        assert!(matches!(
+            table.search("a", 0),
+            SearchResult::Found { record } if record.salary == money("100")
+        ));
+        assert_eq!(0, failed.load(Ordering::SeqCst));
+    }
+
+    // Inserts `old` then `new` for one key under `policy`, returning the
+    // second result and the salary left behind.
+    fn insert_twice(policy: &str, old: &str, new: &str) -> (InsertResult, String) {
+        let table = SalaryTable::new();
+        let policy = parse_conflict_policy(policy).unwrap();
+        table.insert_with("a", old.parse().unwrap(), policy, 0);
+        let result = table.insert_with("a", new.parse().unwrap(), policy, 0);
+        let SearchResult::Found { record } = table.search("a", 0) else {
+            panic!("a is missing");
+        };
+        (result, record.salary.to_string())
+    }
+
+    #[test]
+    fn test_conflict_reject() {
+        let (result, salary) = insert_twice("reject", "10", "20");
+        assert!(matches!(result, InsertResult::Duplicate { .. }));
+        assert_eq!("10", salary);
+    }
+
+    #[test]
+    fn test_conflict_replace() {
+        let (result, salary) = insert_twice("replace", "20", "10");
+        assert!(matches!(result, InsertResult::Merged { .. }));
+        assert_eq!("10", salary);
+    }
+
+    #[test]
+    fn test_conflict_keep_max() {
+        let (result, salary) = insert_twice("keep-max", "20", "10");
+        assert!(matches!(result, InsertResult::Merged { .. }));
+        assert_eq!("20", salary);
+        assert_eq!("30", insert_twice("keep-max", "20", "30").1);
+    }
+
+    #[test]
+    fn test_conflict_sum() {
+        let (result, salary) = insert_twice("sum", "10.25", "20.50");
+        assert!(matches!(result, InsertResult::Merged { .. }));
+        assert_eq!("30.75", salary);
+        // A salary in another currency is not added.
+        assert_eq!("10", insert_twice("sum", "10", "5 EUR").1);
+    }
 }
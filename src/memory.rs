@@ -0,0 +1,156 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cdc::Change;
+use crate::hash::KeyBytes;
+
+// Rough heap cost of one entry in a secondary index (a constraint's map or the
+// ordered skip list), on top of its copy of the key.
+const INDEX_ENTRY_BYTES: usize = 64;
+
+// What to do with an insert that would take the table past its memory limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoryPolicy {
+    #[default]
+    Reject,
+    // Drop the oldest records until the table fits again.
+    Evict,
+}
+
+impl MemoryPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "reject" => Some(MemoryPolicy::Reject),
+            "evict" => Some(MemoryPolicy::Evict),
+            _ => None,
+        }
+    }
+}
+
+// A byte count with an optional k, m or g suffix (powers of 1024).
+pub fn parse_bytes(s: &str) -> Option<usize> {
+    let s = s.trim().to_ascii_lowercase();
+    let (digits, scale) = match s.char_indices().last()? {
+        (i, 'k') => (&s[..i], 1 << 10),
+        (i, 'm') => (&s[..i], 1 << 20),
+        (i, 'g') => (&s[..i], 1 << 30),
+        _ => (&s[..], 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(scale)
+}
+
+// Approximate bytes the table holds, from `memory_usage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub records: usize,
+    pub nodes: usize,
+    pub keys: usize,
+    pub indexes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.nodes + self.keys + self.indexes
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "~{} bytes for {} records (nodes {}, keys {}, indexes {})",
+            self.total(),
+            self.records,
+            self.nodes,
+            self.keys,
+            self.indexes
+        )
+    }
+}
+
+// Running record count and key bytes, kept from committed changes so usage
+// is known without a scan.
+#[derive(Default)]
+pub struct Accounting {
+    records: AtomicUsize,
+    key_bytes: AtomicUsize,
+}
+
+impl Accounting {
+    pub fn apply<K: KeyBytes, V>(&self, change: &Change<K, V>) {
+        match change {
+            Change::Insert { record } => {
+                self.records.fetch_add(1, Ordering::Relaxed);
+                self.key_bytes
+                    .fetch_add(record.name.key_bytes().len(), Ordering::Relaxed);
+            }
+            Change::Delete { record } => {
+                self.records.fetch_sub(1, Ordering::Relaxed);
+                self.key_bytes
+                    .fetch_sub(record.name.key_bytes().len(), Ordering::Relaxed);
+            }
+            // Only a value's inline size is counted, so an update costs nothing.
+            Change::Update { .. } => {}
+        }
+    }
+
+    // Usage with one more record of `extra_key` bytes, for checking an insert
+    // against a limit. Each of `indexes` holds a key copy per record.
+    pub fn usage(
+        &self,
+        node_bytes: usize,
+        indexes: usize,
+        extra_key: Option<usize>,
+    ) -> MemoryUsage {
+        let records = self.records.load(Ordering::Relaxed) + extra_key.map_or(0, |_| 1);
+        let keys = self.key_bytes.load(Ordering::Relaxed) + extra_key.unwrap_or(0);
+        MemoryUsage {
+            records,
+            nodes: records * node_bytes,
+            keys,
+            indexes: indexes * (records * INDEX_ENTRY_BYTES + keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bytes, Accounting};
+    use crate::cdc::Change;
+    use crate::hash_table::HashRecord;
+
+    #[test]
+    fn test_accounting() {
+        assert_eq!(Some(1536), parse_bytes("1536"));
+        assert_eq!(Some(2 << 20), parse_bytes("2M"));
+        assert_eq!(None, parse_bytes("lots"));
+
+        let accounting = Accounting::default();
+        let record = |name: &str| -> HashRecord {
+            HashRecord {
+                hash: 0,
+                name: name.to_string(),
+                salary: "1".parse().unwrap(),
+            }
+        };
+        accounting.apply(&Change::Insert {
+            record: record("Ann"),
+        });
+        accounting.apply(&Change::Insert {
+            record: record("Bobby"),
+        });
+        accounting.apply(&Change::Delete {
+            record: record("Ann"),
+        });
+
+        let usage = accounting.usage(100, 1, None);
+        assert_eq!(
+            (1, 100, 5, 64 + 5),
+            (usage.records, usage.nodes, usage.keys, usage.indexes)
+        );
+        assert_eq!(
+            2 * 100 + 7 + 2 * 64 + 7,
+            accounting.usage(100, 1, Some(2)).total()
+        );
+    }
+}
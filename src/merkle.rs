@@ -0,0 +1,179 @@
+use std::fmt;
+
+use crate::hash::{HashValue, HashWidth};
+use crate::hash_table::{ConflictPolicy, HashRecord, SalaryTable};
+
+// Key ranges the tree summarises; a power of two.
+const LEAVES: usize = 256;
+
+// Which leaf `key` falls in. Fixed rather than the table's own hash, so two
+// copies compare the same way whatever --hash-width each runs with.
+fn leaf_of(key: &str) -> usize {
+    (HashWidth::Bits64.hash(key.as_bytes()) % LEAVES as HashValue) as usize
+}
+
+fn combine(left: HashValue, right: HashValue) -> HashValue {
+    let mut hasher = HashWidth::Bits128.hasher();
+    hasher.update(&left.to_le_bytes());
+    hasher.update(&right.to_le_bytes());
+    hasher.finish()
+}
+
+// A hash tree over the table's records, split into LEAVES key ranges. Two
+// copies hold the same records exactly when their roots match, and where they
+// differ, comparing down from the root finds the ranges to repair without
+// sending every record across.
+pub struct MerkleTree {
+    // Leaf digests first, the root last.
+    levels: Vec<Vec<HashValue>>,
+}
+
+impl MerkleTree {
+    pub fn build(records: &[HashRecord]) -> Self {
+        let mut by_leaf: Vec<Vec<&HashRecord>> = vec![Vec::new(); LEAVES];
+        for record in records {
+            by_leaf[leaf_of(&record.name)].push(record);
+        }
+        let leaves = by_leaf
+            .iter_mut()
+            .map(|records| {
+                records.sort_by(|a, b| a.name.cmp(&b.name));
+                let mut hasher = HashWidth::Bits128.hasher();
+                for record in records.iter() {
+                    hasher.update(record.name.as_bytes());
+                    hasher.update(&[0]);
+                    hasher.update(record.salary.to_string().as_bytes());
+                    hasher.update(&[0]);
+                }
+                hasher.finish()
+            })
+            .collect();
+
+        let mut levels: Vec<Vec<HashValue>> = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let level = levels.last().unwrap();
+            let parents = level.chunks(2).map(|pair| combine(pair[0], pair[1]));
+            levels.push(parents.collect());
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> HashValue {
+        self.levels.last().unwrap()[0]
+    }
+
+    // Leaves that differ from `other`'s, descending only into subtrees whose
+    // digests differ.
+    pub fn diverging(&self, other: &MerkleTree) -> Vec<usize> {
+        let mut nodes = vec![0];
+        for depth in (0..self.levels.len()).rev() {
+            nodes.retain(|&i| self.levels[depth][i] != other.levels[depth][i]);
+            if depth > 0 {
+                nodes = nodes.iter().flat_map(|&i| [2 * i, 2 * i + 1]).collect();
+            }
+        }
+        nodes
+    }
+}
+
+// What an anti-entropy pass found and fixed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Repair {
+    // The source's root digest, which the replica now shares.
+    pub root: HashValue,
+    pub diverging: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} key ranges differed: {} inserted, {} updated, {} deleted (root {:032x})",
+            self.diverging, LEAVES, self.inserted, self.updated, self.deleted, self.root
+        )
+    }
+}
+
+// Makes `replica` hold what `source` holds. Only records in ranges whose
+// digests differ are compared and written, through the replica's normal
+// operations, so its log, constraints and change stream see each repair.
+pub fn anti_entropy(source: &SalaryTable, replica: &SalaryTable, priority: u32) -> Repair {
+    let (wanted, current) = (source.freeze(priority), replica.freeze(priority));
+    let tree = MerkleTree::build(wanted.records());
+    let ranges = tree.diverging(&MerkleTree::build(current.records()));
+    let mut differs = vec![false; LEAVES];
+    for &leaf in ranges.iter() {
+        differs[leaf] = true;
+    }
+    let mut repair = Repair {
+        root: tree.root(),
+        diverging: ranges.len(),
+        ..Repair::default()
+    };
+    let in_ranges = |record: &&HashRecord| differs[leaf_of(&record.name)];
+
+    for record in wanted.records().iter().filter(in_ranges) {
+        match current.get(&record.name) {
+            Some(old) if old.salary == record.salary => continue,
+            Some(_) => repair.updated += 1,
+            None => repair.inserted += 1,
+        }
+        replica.insert_with(
+            &record.name,
+            record.salary,
+            ConflictPolicy::Replace,
+            priority,
+        );
+    }
+    for record in current.records().iter().filter(in_ranges) {
+        if wanted.get(&record.name).is_none() {
+            replica.delete(&record.name, priority);
+            repair.deleted += 1;
+        }
+    }
+    repair
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{anti_entropy, MerkleTree, Repair};
+    use crate::hash_table::SalaryTable;
+    use crate::logger::ThreadLogger;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_anti_entropy() {
+        let path = std::env::temp_dir().join("cht_test_anti_entropy.log");
+        let logger = Arc::new(ThreadLogger::new(path.to_str().unwrap()));
+        let (source, replica) = (
+            SalaryTable::with_logger(logger.clone()),
+            SalaryTable::with_logger(logger),
+        );
+        for i in 0..1000 {
+            let salary = i.to_string().parse().unwrap();
+            source.insert(&format!("k{}", i), salary, 0);
+            replica.insert(&format!("k{}", i), salary, 0);
+        }
+        let tree = |table: &SalaryTable| MerkleTree::build(table.freeze(0).records());
+        assert_eq!(tree(&source).root(), tree(&replica).root());
+
+        replica.delete("k1", 0);
+        replica.update_salary("k2", "7".parse().unwrap(), 0);
+        replica.insert("extra", "1".parse().unwrap(), 0);
+        assert!(tree(&source).diverging(&tree(&replica)).len() <= 3);
+
+        let expected = Repair {
+            root: tree(&source).root(),
+            diverging: tree(&source).diverging(&tree(&replica)).len(),
+            inserted: 1,
+            updated: 1,
+            deleted: 1,
+        };
+        assert_eq!(expected, anti_entropy(&source, &replica, 0));
+        assert_eq!(tree(&source).root(), tree(&replica).root());
+        assert_eq!(0, anti_entropy(&source, &replica, 0).diverging);
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// One slot per second; windows longer than this are clamped.
+const SLOT_COUNT: usize = 60;
+// A lock wait past this counts as contended.
+const CONTENDED_WAIT: Duration = Duration::from_micros(50);
+// More stripes are advised once this share of acquisitions is contended...
+const CONTENDED_SHARE: f64 = 0.1;
+// ...unless one stripe has more than this share of the contended ones: a few
+// hot keys, which more stripes would not split up.
+const HOT_STRIPE_SHARE: f64 = 0.5;
+// An SLO's p99 is over its target once more than this share of operations are.
+const SLO_ALLOWANCE: f64 = 0.01;
+// A p99 says little about fewer operations than this, so no alert is raised.
+const SLO_MIN_OPS: u64 = 100;
+const DEFAULT_SLO_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    ops: u64,
+    lookups: u64,
+    hits: u64,
+    lock_wait_us: u64,
+}
+
+impl Counts {
+    fn add(&mut self, hit: Option<bool>, lock_wait: Duration) {
+        self.ops += 1;
+        self.lock_wait_us += lock_wait.as_micros() as u64;
+        if let Some(hit) = hit {
+            self.lookups += 1;
+            self.hits += hit as u64;
+        }
+    }
+
+    fn hit_rate(&self) -> Option<f64> {
+        (self.lookups > 0).then(|| self.hits as f64 / self.lookups as f64)
+    }
+
+    fn avg_lock_wait(&self) -> Duration {
+        Duration::from_micros(self.lock_wait_us.checked_div(self.ops).unwrap_or(0))
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    second: u64,
+    counts: Counts,
+}
+
+// Per-second counters kept in a ring. Slots are recycled lazily when an
+// operation lands in a new second, so no background thread is needed.
+// Tagged operations are also counted per tag for the whole run.
+pub struct Metrics {
+    started: Instant,
+    slots: Mutex<[Slot; SLOT_COUNT]>,
+    by_tag: Mutex<HashMap<&'static str, Counts>>,
+}
+
+pub struct WindowStats {
+    pub window: Duration,
+    pub ops: u64,
+    pub ops_per_sec: f64,
+    // None when no lookups happened in the window.
+    pub hit_rate: Option<f64>,
+    pub avg_lock_wait: Duration,
+}
+
+fn format_hit_rate(hit_rate: Option<f64>) -> String {
+    hit_rate.map_or("n/a".to_string(), |rate| format!("{:.1}%", rate * 100.0))
+}
+
+impl fmt::Display for WindowStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}s: {} ops, {:.1} ops/sec, hit rate {}, avg lock wait {}us",
+            self.window.as_secs(),
+            self.ops,
+            self.ops_per_sec,
+            format_hit_rate(self.hit_rate),
+            self.avg_lock_wait.as_micros()
+        )
+    }
+}
+
+// Totals for one tag over the whole run.
+pub struct TagStats {
+    pub tag: &'static str,
+    pub ops: u64,
+    pub hit_rate: Option<f64>,
+    pub avg_lock_wait: Duration,
+}
+
+impl fmt::Display for TagStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ops, hit rate {}, avg lock wait {}us",
+            self.tag,
+            self.ops,
+            format_hit_rate(self.hit_rate),
+            self.avg_lock_wait.as_micros()
+        )
+    }
+}
+
+// How evenly keys spread, from a scan of the table.
+pub struct HashQuality {
+    pub records: usize,
+    // Records sharing a hash with an earlier, different key.
+    pub collisions: usize,
+    pub chain_lengths: Vec<usize>,
+}
+
+impl HashQuality {
+    pub fn mean_chain_length(&self) -> f64 {
+        self.records as f64 / self.chain_lengths.len().max(1) as f64
+    }
+
+    pub fn chain_length_variance(&self) -> f64 {
+        let mean = self.mean_chain_length();
+        let squares: f64 = self
+            .chain_lengths
+            .iter()
+            .map(|&len| (len as f64 - mean).powi(2))
+            .sum();
+        squares / self.chain_lengths.len().max(1) as f64
+    }
+
+    // More than one key in a thousand colliding, or chains spread much less
+    // evenly than random placement would (where variance is about the mean).
+    pub fn is_poor(&self) -> bool {
+        self.collisions > self.records / 1_000
+            || (self.chain_lengths.len() > 1
+                && self.chain_length_variance() > 2.0 * self.mean_chain_length().max(1.0))
+    }
+}
+
+impl fmt::Display for HashQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} records, {} collisions, {} chains, chain length mean {:.1} variance {:.1}",
+            self.records,
+            self.collisions,
+            self.chain_lengths.len(),
+            self.mean_chain_length(),
+            self.chain_length_variance()
+        )
+    }
+}
+
+// Single-key lock acquisitions on one stripe, and how many were contended.
+#[derive(Default)]
+pub struct StripeLoad {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+}
+
+impl StripeLoad {
+    pub fn record(&self, lock_wait: Duration) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if lock_wait > CONTENDED_WAIT {
+            self.contended.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// A stripe count for the table's single-key traffic so far, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StripeAdvice {
+    pub current: usize,
+    pub recommended: usize,
+    pub acquisitions: u64,
+    pub contended: u64,
+    // The stripe with the most contended acquisitions, and its share of them.
+    pub hottest: Option<(usize, f64)>,
+}
+
+impl StripeAdvice {
+    // Doubles the stripes while too many acquisitions wait, except when the
+    // waits pile up on one stripe.
+    pub fn from_loads<'a>(loads: impl IntoIterator<Item = &'a StripeLoad>) -> Self {
+        let counts: Vec<(u64, u64)> = loads
+            .into_iter()
+            .map(|load| {
+                (
+                    load.acquisitions.load(Ordering::Relaxed),
+                    load.contended.load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        let acquisitions = counts.iter().map(|(all, _)| all).sum();
+        let contended: u64 = counts.iter().map(|(_, contended)| contended).sum();
+        let hottest = (contended > 0).then(|| {
+            let (stripe, (_, most)) = counts
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, (_, contended))| *contended)
+                .unwrap();
+            (stripe, *most as f64 / contended as f64)
+        });
+
+        let current = counts.len();
+        let busy = contended as f64 > CONTENDED_SHARE * acquisitions as f64;
+        let hot = current > 1 && hottest.is_some_and(|(_, share)| share > HOT_STRIPE_SHARE);
+        StripeAdvice {
+            current,
+            recommended: if busy && !hot { current * 2 } else { current },
+            acquisitions,
+            contended,
+            hottest,
+        }
+    }
+}
+
+impl fmt::Display for StripeAdvice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.recommended == self.current {
+            "keep"
+        } else {
+            "grow"
+        };
+        write!(
+            f,
+            "{} stripes -> {} ({}): {} of {} single-key lock acquisitions waited over {}us",
+            self.current,
+            self.recommended,
+            verdict,
+            self.contended,
+            self.acquisitions,
+            CONTENDED_WAIT.as_micros()
+        )?;
+        if let Some((stripe, share)) = self.hottest.filter(|_| self.current > 1) {
+            write!(f, ", {:.0}% of them on stripe {}", share * 100.0, stripe)?;
+            if share > HOT_STRIPE_SHARE {
+                write!(f, " (hot keys; more stripes would not spread them)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A latency target for one kind of operation ("SEARCH", "INSERT", ...): its
+// p99 over the last `window`, lock wait included, should be within `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slo {
+    pub op: String,
+    pub target: Duration,
+    pub window: Duration,
+}
+
+impl Slo {
+    // "<op>:<target us>[:<window s>]", e.g. "search:200:10". The window is
+    // whole seconds, up to one minute, and 10s when left out.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut parts = spec.split(':');
+        let op = parts.next().filter(|op| !op.is_empty())?.to_uppercase();
+        let target = Duration::from_micros(parts.next()?.trim().parse().ok()?);
+        let window = match parts.next() {
+            Some(seconds) => Duration::from_secs(seconds.trim().parse().ok()?),
+            None => DEFAULT_SLO_WINDOW,
+        };
+        let seconds = window.as_secs();
+        if parts.next().is_some() || seconds == 0 || seconds > SLOT_COUNT as u64 {
+            return None;
+        }
+        Some(Slo { op, target, window })
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct SloSlot {
+    second: u64,
+    ops: u64,
+    over: u64,
+}
+
+struct SloState {
+    slots: [SloSlot; SLOT_COUNT],
+    // Over target for the whole run.
+    violations: u64,
+    breached: bool,
+    // When `breached` last changed, so an op near the line flips it at most
+    // once a second rather than alerting on every operation.
+    changed: Option<u64>,
+}
+
+// How an SLO stands over its window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SloStatus {
+    pub slo: Slo,
+    pub ops: u64,
+    pub over: u64,
+    pub violations: u64,
+    pub breached: bool,
+}
+
+impl fmt::Display for SloStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} p99 {} {}us: {} of {} ops in the last {}s took longer, {} in all",
+            self.slo.op,
+            if self.breached { "over" } else { "within" },
+            self.slo.target.as_micros(),
+            self.over,
+            self.ops,
+            self.slo.window.as_secs(),
+            self.violations
+        )
+    }
+}
+
+// Per-second counts of the SLO's operations and those over target, in a ring
+// like Metrics'.
+pub struct SloTracker {
+    slo: Slo,
+    started: Instant,
+    state: Mutex<SloState>,
+}
+
+impl SloTracker {
+    pub fn new(slo: Slo) -> Self {
+        SloTracker {
+            slo,
+            started: Instant::now(),
+            state: Mutex::new(SloState {
+                slots: [SloSlot::default(); SLOT_COUNT],
+                violations: 0,
+                breached: false,
+                changed: None,
+            }),
+        }
+    }
+
+    pub fn op(&self) -> &str {
+        &self.slo.op
+    }
+
+    // Counts one operation. Returns the new status when it moves the p99 over
+    // the target or back within it.
+    pub fn record(&self, latency: Duration) -> Option<SloStatus> {
+        self.record_at(self.started.elapsed().as_secs(), latency)
+    }
+
+    // `record`, as if `second` seconds into the run. The second is read before
+    // the lock, so another thread may already have counted a later one.
+    fn record_at(&self, second: u64, latency: Duration) -> Option<SloStatus> {
+        let over = latency > self.slo.target;
+        let mut state = self.state.lock().unwrap();
+        let slot = &mut state.slots[second as usize % SLOT_COUNT];
+        if slot.second != second {
+            *slot = SloSlot {
+                second,
+                ..SloSlot::default()
+            };
+        }
+        slot.ops += 1;
+        slot.over += over as u64;
+        state.violations += over as u64;
+
+        if state.changed == Some(second) {
+            return None;
+        }
+        let status = self.status_of(&state, second);
+        let breached =
+            status.ops >= SLO_MIN_OPS && status.over as f64 > SLO_ALLOWANCE * status.ops as f64;
+        if breached == state.breached {
+            return None;
+        }
+        state.breached = breached;
+        state.changed = Some(second);
+        Some(SloStatus { breached, ..status })
+    }
+
+    pub fn status(&self) -> SloStatus {
+        let state = self.state.lock().unwrap();
+        self.status_of(&state, self.started.elapsed().as_secs())
+    }
+
+    fn status_of(&self, state: &SloState, now: u64) -> SloStatus {
+        let seconds = self.slo.window.as_secs();
+        let (mut ops, mut over) = (0, 0);
+        for slot in state
+            .slots
+            .iter()
+            .filter(|s| s.ops > 0 && s.second <= now && now - s.second < seconds)
+        {
+            ops += slot.ops;
+            over += slot.over;
+        }
+        SloStatus {
+            slo: self.slo.clone(),
+            ops,
+            over,
+            violations: state.violations,
+            breached: state.breached,
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            slots: Mutex::new([Slot::default(); SLOT_COUNT]),
+            by_tag: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // `hit` is Some for lookups (search/update/delete) and None otherwise.
+    pub fn record(&self, tag: Option<&'static str>, hit: Option<bool>, lock_wait: Duration) {
+        self.record_at(self.started.elapsed().as_secs(), tag, hit, lock_wait);
+    }
+
+    // `record`, as if `second` seconds into the run.
+    fn record_at(
+        &self,
+        second: u64,
+        tag: Option<&'static str>,
+        hit: Option<bool>,
+        lock_wait: Duration,
+    ) {
+        {
+            let mut slots = self.slots.lock().unwrap();
+            let slot = &mut slots[second as usize % SLOT_COUNT];
+            if slot.second != second {
+                *slot = Slot {
+                    second,
+                    ..Slot::default()
+                };
+            }
+            slot.counts.add(hit, lock_wait);
+        }
+        if let Some(tag) = tag {
+            let mut by_tag = self.by_tag.lock().unwrap();
+            by_tag.entry(tag).or_default().add(hit, lock_wait);
+        }
+    }
+
+    // Sorted by tag.
+    pub fn by_tag(&self) -> Vec<TagStats> {
+        let mut stats: Vec<TagStats> = self
+            .by_tag
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tag, counts)| TagStats {
+                tag,
+                ops: counts.ops,
+                hit_rate: counts.hit_rate(),
+                avg_lock_wait: counts.avg_lock_wait(),
+            })
+            .collect();
+        stats.sort_by_key(|stats| stats.tag);
+        stats
+    }
+
+    // Totals over the most recent `window`, rounded up to whole seconds.
+    pub fn window(&self, window: Duration) -> WindowStats {
+        self.window_at(self.started.elapsed().as_secs(), window)
+    }
+
+    // `window`, as seen `now` seconds into the run. `now` is read before the
+    // lock, so slots may already hold a later second; those are skipped.
+    fn window_at(&self, now: u64, window: Duration) -> WindowStats {
+        let seconds = window.as_secs_f64().ceil().clamp(1.0, SLOT_COUNT as f64) as u64;
+        let slots = self.slots.lock().unwrap();
+
+        let mut total = Counts::default();
+        for slot in slots
+            .iter()
+            .filter(|s| s.counts.ops > 0 && s.second <= now && now - s.second < seconds)
+        {
+            total.ops += slot.counts.ops;
+            total.lookups += slot.counts.lookups;
+            total.hits += slot.counts.hits;
+            total.lock_wait_us += slot.counts.lock_wait_us;
+        }
+
+        WindowStats {
+            window: Duration::from_secs(seconds),
+            ops: total.ops,
+            ops_per_sec: total.ops as f64 / seconds as f64,
+            hit_rate: total.hit_rate(),
+            avg_lock_wait: total.avg_lock_wait(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HashQuality, Metrics, Slo, SloTracker, StripeAdvice, StripeLoad};
+    use std::time::Duration;
+
+    #[test]
+    fn test_tag_stats() {
+        let metrics = Metrics::new();
+        metrics.record(Some("api"), Some(true), Duration::from_micros(10));
+        metrics.record(Some("api"), Some(false), Duration::from_micros(30));
+        metrics.record(Some("ingest"), None, Duration::ZERO);
+        metrics.record(None, None, Duration::ZERO);
+
+        let by_tag: Vec<String> = metrics.by_tag().iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            vec![
+                "api: 2 ops, hit rate 50.0%, avg lock wait 20us",
+                "ingest: 1 ops, hit rate n/a, avg lock wait 0us"
+            ],
+            by_tag
+        );
+        assert_eq!(4, metrics.window(Duration::from_secs(60)).ops);
+    }
+
+    #[test]
+    fn test_window() {
+        let metrics = Metrics::new();
+        let wait = Duration::from_micros(10);
+        for second in [0, 0, 5, 9] {
+            metrics.record_at(second, None, Some(second == 0), wait);
+        }
+        let ops = |now, seconds| metrics.window_at(now, Duration::from_secs(seconds)).ops;
+        // Second 9 looks back over 9, then 5 too, then 0 too.
+        assert_eq!((1, 2, 4), (ops(9, 1), ops(9, 5), ops(9, 10)));
+        // Windows are clamped to the ring: at least a second, at most a minute.
+        assert_eq!(1, ops(9, 0));
+        assert_eq!(4, ops(9, 3600));
+
+        let stats = metrics.window_at(9, Duration::from_millis(9_500));
+        assert_eq!(10, stats.window.as_secs());
+        assert_eq!(0.4, stats.ops_per_sec);
+        assert_eq!(Some(0.5), stats.hit_rate);
+        assert_eq!(wait, stats.avg_lock_wait);
+
+        // Once the ring comes round, second 60 reuses second 0's slot.
+        metrics.record_at(60, None, None, wait);
+        assert_eq!(3, ops(60, 60));
+        assert_eq!(1, ops(60, 1));
+        // Seconds that have fallen out of the window count for nothing.
+        assert_eq!(0, ops(120, 30));
+        // A window read before second 60 was recorded leaves it out.
+        assert_eq!(0, ops(59, 1));
+    }
+
+    #[test]
+    fn test_hash_quality() {
+        let even = HashQuality {
+            records: 8,
+            collisions: 0,
+            chain_lengths: vec![2, 2, 2, 2],
+        };
+        assert_eq!(0.0, even.chain_length_variance());
+        assert!(!even.is_poor());
+
+        let skewed = HashQuality {
+            chain_lengths: vec![8, 0, 0, 0],
+            ..even
+        };
+        assert_eq!(12.0, skewed.chain_length_variance());
+        assert!(skewed.is_poor());
+
+        let colliding = HashQuality {
+            records: 10,
+            collisions: 1,
+            chain_lengths: vec![10],
+        };
+        assert!(colliding.is_poor());
+    }
+
+    #[test]
+    fn test_stripe_advice() {
+        let (quick, slow) = (Duration::ZERO, Duration::from_millis(1));
+        let record = |load: &StripeLoad, waits: &[(Duration, usize)]| {
+            for &(wait, times) in waits {
+                (0..times).for_each(|_| load.record(wait));
+            }
+        };
+
+        // Most acquisitions on the only stripe waited.
+        let one = [StripeLoad::default()];
+        record(&one[0], &[(slow, 30), (quick, 70)]);
+        let advice = StripeAdvice::from_loads(&one);
+        assert_eq!(
+            (1, 2, 100, 30),
+            (
+                advice.current,
+                advice.recommended,
+                advice.acquisitions,
+                advice.contended
+            )
+        );
+        assert_eq!(
+            "1 stripes -> 2 (grow): 30 of 100 single-key lock acquisitions waited over 50us",
+            advice.to_string()
+        );
+
+        // The waits are all on one of four stripes.
+        let four: Vec<StripeLoad> = (0..4).map(|_| StripeLoad::default()).collect();
+        record(&four[2], &[(slow, 40), (quick, 10)]);
+        record(&four[0], &[(quick, 50)]);
+        let advice = StripeAdvice::from_loads(&four);
+        assert_eq!(4, advice.recommended);
+        assert!(advice
+            .to_string()
+            .ends_with("100% of them on stripe 2 (hot keys; more stripes would not spread them)"));
+
+        // Quiet stripes stay as they are.
+        let quiet = [StripeLoad::default(), StripeLoad::default()];
+        record(&quiet[1], &[(quick, 10)]);
+        assert_eq!(2, StripeAdvice::from_loads(&quiet).recommended);
+    }
+
+    #[test]
+    fn test_slo() {
+        let slo = Slo::parse("search:200").unwrap();
+        assert_eq!(
+            ("SEARCH", 200, 10),
+            (
+                slo.op.as_str(),
+                slo.target.as_micros(),
+                slo.window.as_secs()
+            )
+        );
+        assert_eq!(60, Slo::parse("insert:5:60").unwrap().window.as_secs());
+        for bad in [
+            "",
+            ":5",
+            "search",
+            "search:x",
+            "search:5:0",
+            "search:5:61",
+            "search:5:1:1",
+        ] {
+            assert!(Slo::parse(bad).is_none(), "{}", bad);
+        }
+
+        let tracker = SloTracker::new(slo);
+        let (quick, slow) = (Duration::from_micros(10), Duration::from_millis(1));
+        // A slow start is not an alert: too few operations to call it a p99.
+        assert!(tracker.record(slow).is_none());
+        for _ in 0..99 {
+            assert!(tracker.record(quick).is_none());
+        }
+        // 1 of 100 over is still a p99 within target; 2 of 101 is not.
+        let alert = tracker.record(slow).unwrap();
+        assert!(alert.breached);
+        assert_eq!((101, 2, 2), (alert.ops, alert.over, alert.violations));
+        assert_eq!(
+            "SEARCH p99 over 200us: 2 of 101 ops in the last 10s took longer, 2 in all",
+            alert.to_string()
+        );
+        // Once alerted, the same second raises nothing more.
+        assert!(tracker.record(slow).is_none());
+        assert!(tracker.status().breached);
+    }
+
+    #[test]
+    fn test_slo_late_second() {
+        let tracker = SloTracker::new(Slo::parse("search:200").unwrap());
+        let quick = Duration::from_micros(10);
+        // A thread that read second 5 gets the lock after one that read 6.
+        assert!(tracker.record_at(6, quick).is_none());
+        assert!(tracker.record_at(5, quick).is_none());
+        let state = tracker.state.lock().unwrap();
+        // Second 6 is ahead of 5, so it is not counted.
+        assert_eq!(1, tracker.status_of(&state, 5).ops);
+        assert_eq!(2, tracker.status_of(&state, 6).ops);
+    }
+}
@@ -0,0 +1,281 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// Minor units (cents) per major unit. Every currency here uses two decimals.
+const MINOR_PER_MAJOR: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    // Amounts written without a code are in this currency.
+    pub const DEFAULT: Currency = Currency(*b"USD");
+
+    // A three-letter ISO 4217 style code such as "EUR".
+    pub fn parse(code: &str) -> Option<Self> {
+        let bytes: [u8; 3] = code.as_bytes().try_into().ok()?;
+        bytes
+            .iter()
+            .all(u8::is_ascii_uppercase)
+            .then_some(Currency(bytes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ASCII uppercase.
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    Invalid { input: String },
+    Overflow,
+    CurrencyMismatch { left: Currency, right: Currency },
+    // Adjusting a table value that is not an amount.
+    NotAmount,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Invalid { input } => write!(f, "invalid amount: {}", input),
+            MoneyError::Overflow => write!(f, "amount out of range"),
+            MoneyError::NotAmount => write!(f, "value is not an amount"),
+            MoneyError::CurrencyMismatch { left, right } => {
+                write!(
+                    f,
+                    "cannot combine {} with {}",
+                    left.as_str(),
+                    right.as_str()
+                )
+            }
+        }
+    }
+}
+
+// A fixed-point amount in minor units: 710.50 EUR is stored as 71050 and EUR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+// What adding to an amount does when the result does not fit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Fail with MoneyError::Overflow.
+    #[default]
+    Checked,
+    // Clamp to the largest or smallest amount.
+    Saturating,
+    // Wrap around in two's complement.
+    Wrapping,
+}
+
+impl OverflowPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "checked" => Some(OverflowPolicy::Checked),
+            "saturating" => Some(OverflowPolicy::Saturating),
+            "wrapping" => Some(OverflowPolicy::Wrapping),
+            _ => None,
+        }
+    }
+}
+
+// Amounts in different currencies are unordered.
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (self.currency == other.currency).then(|| self.minor.cmp(&other.minor))
+    }
+}
+
+impl Money {
+    pub fn from_minor(minor: i64, currency: Currency) -> Money {
+        Money { minor, currency }
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn negated(self) -> Money {
+        Money {
+            minor: self.minor.saturating_neg(),
+            ..self
+        }
+    }
+
+    // Grows (or shrinks) the amount by `basis_points` hundredths of a percent,
+    // rounding half away from zero to the nearest cent.
+    pub fn scale_percent(self, basis_points: i64) -> Result<Money, MoneyError> {
+        let change = self.minor as i128 * basis_points as i128;
+        let rounded = (change + change.signum() * 5_000) / 10_000;
+        let minor =
+            i64::try_from(self.minor as i128 + rounded).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money { minor, ..self })
+    }
+
+    // Adds `delta`, which must be in the same currency.
+    pub fn add(self, delta: Money, policy: OverflowPolicy) -> Result<Money, MoneyError> {
+        if self.currency != delta.currency {
+            return Err(MoneyError::CurrencyMismatch {
+                left: self.currency,
+                right: delta.currency,
+            });
+        }
+        let minor = match policy {
+            OverflowPolicy::Checked => self
+                .minor
+                .checked_add(delta.minor)
+                .ok_or(MoneyError::Overflow)?,
+            OverflowPolicy::Saturating => self.minor.saturating_add(delta.minor),
+            OverflowPolicy::Wrapping => self.minor.wrapping_add(delta.minor),
+        };
+        Ok(Money { minor, ..self })
+    }
+}
+
+// "<amount>[ <CODE>]" where amount is "-"? digits, optionally followed by "." and
+// one or two decimals: "71000", "710.5", "-3.25 EUR".
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MoneyError::Invalid {
+            input: s.to_string(),
+        };
+
+        let (amount, currency) = match s.trim().split_once(' ') {
+            Some((amount, code)) => (amount, Currency::parse(code.trim()).ok_or_else(invalid)?),
+            None => (s.trim(), Currency::DEFAULT),
+        };
+        let minor = parse_hundredths(amount).map_err(|e| match e {
+            MoneyError::Invalid { .. } => invalid(),
+            e => e,
+        })?;
+        Ok(Money { minor, currency })
+    }
+}
+
+// "-"? digits with up to two decimals, in hundredths: "710.5" is 71050.
+fn parse_hundredths(s: &str) -> Result<i64, MoneyError> {
+    let invalid = || MoneyError::Invalid {
+        input: s.to_string(),
+    };
+    let (negative, amount) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (major, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+
+    let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if major.is_empty() || !all_digits(major) || fraction.len() > 2 || !all_digits(fraction) {
+        return Err(invalid());
+    }
+
+    let major: i64 = major.parse().map_err(|_| MoneyError::Overflow)?;
+    let cents = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<i64>().unwrap() * 10,
+        _ => fraction.parse::<i64>().unwrap(),
+    };
+    let minor = major
+        .checked_mul(MINOR_PER_MAJOR)
+        .and_then(|m| m.checked_add(cents))
+        .ok_or(MoneyError::Overflow)?;
+    Ok(if negative { -minor } else { minor })
+}
+
+// A percentage such as "3.5" in basis points (350).
+pub fn parse_percent(s: &str) -> Option<i64> {
+    parse_hundredths(s.trim()).ok()
+}
+
+// Whole amounts print without decimals and the default currency without its
+// code, so plain salaries read exactly as they were written.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let major = self.minor.unsigned_abs() / MINOR_PER_MAJOR as u64;
+        let cents = self.minor.unsigned_abs() % MINOR_PER_MAJOR as u64;
+
+        write!(f, "{}{}", sign, major)?;
+        if cents != 0 {
+            write!(f, ".{:02}", cents)?;
+        }
+        if self.currency != Currency::DEFAULT {
+            write!(f, " {}", self.currency.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Money, MoneyError, OverflowPolicy};
+
+    #[test]
+    fn test_parse_and_display() {
+        for (input, minor, display) in [
+            ("71000", 7_100_000, "71000"),
+            ("710.5", 71_050, "710.50"),
+            ("-3.25 EUR", -325, "-3.25 EUR"),
+            ("12.00", 1_200, "12"),
+        ] {
+            let money: Money = input.parse().unwrap();
+            assert_eq!(minor, money.minor);
+            assert_eq!(display, money.to_string());
+        }
+
+        assert!(matches!(
+            "1.234".parse::<Money>(),
+            Err(MoneyError::Invalid { .. })
+        ));
+        assert!(matches!(
+            "5 eur".parse::<Money>(),
+            Err(MoneyError::Invalid { .. })
+        ));
+        assert_eq!(
+            Err(MoneyError::Overflow),
+            "92233720368547758070".parse::<Money>()
+        );
+    }
+
+    #[test]
+    fn test_add() {
+        let money = |s: &str| s.parse::<Money>().unwrap();
+        let max = Money {
+            minor: i64::MAX,
+            ..money("0")
+        };
+
+        assert_eq!(
+            Ok(money("10.25")),
+            money("10").add(money("0.25"), OverflowPolicy::Checked)
+        );
+        assert_eq!(
+            Err(MoneyError::Overflow),
+            max.add(money("0.01"), OverflowPolicy::Checked)
+        );
+        assert_eq!(Ok(max), max.add(money("1"), OverflowPolicy::Saturating));
+        assert_eq!(
+            Ok(i64::MIN),
+            max.add(money("0.01"), OverflowPolicy::Wrapping)
+                .map(|m| m.minor)
+        );
+        assert_eq!(Ok(money("103.50")), money("100").scale_percent(350));
+        assert_eq!(Ok(money("0.02")), money("0.01").scale_percent(5_000));
+        assert!(money("1") < money("1.01"));
+        assert_eq!(None, money("1").partial_cmp(&money("1 EUR")));
+        assert!(matches!(
+            money("1").add(money("1 EUR"), OverflowPolicy::Checked),
+            Err(MoneyError::CurrencyMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use crate::cdc::Change;
+use crate::hash::KeyBytes;
+use crate::hash_table::{self, HashRecord};
+use crate::money::Money;
+
+const MAX_LEVEL: usize = 16;
+
+struct Entry<K, V> {
+    record: HashRecord<K, V>,
+    // The next entry at each level this entry is linked into.
+    next: Vec<Option<usize>>,
+}
+
+// A skip list over record keys. Entries live in an arena and link by index;
+// removed slots are reused.
+struct SkipList<K, V> {
+    entries: Vec<Option<Entry<K, V>>>,
+    free: Vec<usize>,
+    head: [Option<usize>; MAX_LEVEL],
+}
+
+impl<K: Ord + Clone, V: Clone> SkipList<K, V> {
+    fn new() -> Self {
+        SkipList {
+            entries: Vec::new(),
+            free: Vec::new(),
+            head: [None; MAX_LEVEL],
+        }
+    }
+
+    fn entry(&self, i: usize) -> &Entry<K, V> {
+        self.entries[i].as_ref().unwrap()
+    }
+
+    fn key(&self, i: usize) -> &K {
+        &self.entry(i).record.name
+    }
+
+    fn next(&self, at: Option<usize>, level: usize) -> Option<usize> {
+        match at {
+            None => self.head[level],
+            Some(i) => self.entry(i).next[level],
+        }
+    }
+
+    fn set_next(&mut self, at: Option<usize>, level: usize, to: Option<usize>) {
+        match at {
+            None => self.head[level] = to,
+            Some(i) => self.entries[i].as_mut().unwrap().next[level] = to,
+        }
+    }
+
+    // The last entry before `key` at every level; None is the head.
+    fn predecessors<Q: Ord + ?Sized>(&self, key: &Q) -> [Option<usize>; MAX_LEVEL]
+    where
+        K: Borrow<Q>,
+    {
+        let mut preds = [None; MAX_LEVEL];
+        let mut at = None;
+        for level in (0..MAX_LEVEL).rev() {
+            while let Some(next) = self.next(at, level) {
+                if self.key(next).borrow() >= key {
+                    break;
+                }
+                at = Some(next);
+            }
+            preds[level] = at;
+        }
+        preds
+    }
+
+    fn upsert(&mut self, record: HashRecord<K, V>) {
+        let preds = self.predecessors(&record.name);
+        if let Some(i) = self
+            .next(preds[0], 0)
+            .filter(|&i| *self.key(i) == record.name)
+        {
+            self.entries[i].as_mut().unwrap().record = record;
+            return;
+        }
+
+        // The hash's low bits are already uniform, so they pick the height
+        // (one level in two goes up) without a random number generator.
+        let height = (record.hash.trailing_ones() as usize + 1).min(MAX_LEVEL);
+        let entry = Entry {
+            record,
+            next: (0..height)
+                .map(|level| self.next(preds[level], level))
+                .collect(),
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.entries[i] = Some(entry);
+                i
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        };
+        for (level, pred) in preds.iter().enumerate().take(height) {
+            self.set_next(*pred, level, Some(i));
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        let preds = self.predecessors(key);
+        let Some(i) = self.next(preds[0], 0).filter(|&i| self.key(i) == key) else {
+            return;
+        };
+        for (level, pred) in preds.iter().enumerate().take(self.entry(i).next.len()) {
+            let next = self.entry(i).next[level];
+            self.set_next(*pred, level, next);
+        }
+        self.entries[i] = None;
+        self.free.push(i);
+    }
+
+    fn range<Q: Ord + ?Sized>(&self, from: &Q, to: &Q) -> Vec<HashRecord<K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let mut records = Vec::new();
+        let mut at = self.next(self.predecessors(from)[0], 0);
+        while let Some(i) = at.filter(|&i| self.key(i).borrow() < to) {
+            records.push(self.entry(i).record.clone());
+            at = self.entry(i).next[0];
+        }
+        records
+    }
+}
+
+// Every record in key order, kept up to date from committed changes so range
+// reads need not sort the table. One skip list per table stripe, holding that
+// stripe's keys, so writers to different stripes never wait on each other
+// here; a range merges them.
+pub struct OrderedIndex<K = String, V = Money> {
+    // Each only written while its stripe's write lock is held.
+    shards: Vec<Mutex<SkipList<K, V>>>,
+}
+
+impl<K: Ord + Clone + KeyBytes, V: Clone> OrderedIndex<K, V> {
+    // For a table of `stripes` stripes.
+    pub fn new(stripes: usize) -> Self {
+        OrderedIndex {
+            shards: (0..stripes.max(1))
+                .map(|_| Mutex::new(SkipList::new()))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<SkipList<K, V>> {
+        &self.shards[hash_table::stripe_of(key, self.shards.len())]
+    }
+
+    pub fn apply(&self, change: &Change<K, V>) {
+        match change {
+            Change::Insert { record }
+            | Change::Update {
+                new_record: record, ..
+            } => self
+                .shard(&record.name)
+                .lock()
+                .unwrap()
+                .upsert(record.clone()),
+            Change::Delete { record } => self
+                .shard(&record.name)
+                .lock()
+                .unwrap()
+                .remove(&record.name),
+        }
+    }
+
+    // Moves every record to its shard for a table of `stripes` stripes.
+    pub fn restripe(&mut self, stripes: usize) {
+        let old = std::mem::replace(self, OrderedIndex::new(stripes));
+        for shard in old.shards {
+            for entry in shard.into_inner().unwrap().entries.into_iter().flatten() {
+                self.apply(&Change::Insert {
+                    record: entry.record,
+                });
+            }
+        }
+    }
+
+    // Records with keys in `from..to`, in key order. Consistent across shards
+    // only while the caller holds every stripe.
+    pub fn range<Q: Ord + ?Sized>(&self, from: &Q, to: &Q) -> Vec<HashRecord<K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let mut records: Vec<HashRecord<K, V>> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.lock().unwrap().range(from, to))
+            .collect();
+        // Each shard's run is sorted already.
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedIndex;
+    use crate::cdc::Change;
+    use crate::hash::HashValue;
+    use crate::hash_table::HashRecord;
+
+    fn record(hash: HashValue, name: &str) -> HashRecord {
+        HashRecord {
+            hash,
+            name: name.to_string(),
+            salary: "1".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_range() {
+        let index: OrderedIndex = OrderedIndex::new(3);
+        for (i, name) in ["m", "c", "x", "a", "q", "e"].iter().enumerate() {
+            index.apply(&Change::Insert {
+                record: record(i as HashValue * 7, name),
+            });
+        }
+        index.apply(&Change::Delete {
+            record: record(0, "m"),
+        });
+
+        let keys = |from, to| -> Vec<String> {
+            index.range(from, to).into_iter().map(|r| r.name).collect()
+        };
+        assert_eq!(vec!["c", "e", "q"], keys("b", "x"));
+        assert_eq!(vec!["a", "c", "e", "q", "x"], keys("", "~"));
+        assert!(keys("f", "p").is_empty());
+    }
+}
@@ -0,0 +1,537 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::hash::HashValue;
+use crate::hash_table::{Explanation, HashRecord, RecordMeta};
+use crate::histogram::SalaryHistogram;
+use crate::ingest::IngestStats;
+use crate::logger::LockType;
+use crate::memory::MemoryUsage;
+use crate::money::{Money, MoneyError};
+use crate::similar::SimilarKeys;
+
+// What a command printed, kept typed until the collector renders it.
+pub enum CommandOutcome {
+    Inserted {
+        record: HashRecord,
+    },
+    Duplicate {
+        hash: HashValue,
+        name: String,
+    },
+    Merged {
+        old_record: HashRecord,
+        new_record: HashRecord,
+    },
+    Deleted {
+        record: HashRecord,
+    },
+    DeleteNotFound {
+        name: String,
+    },
+    PopEmpty,
+    OutOfMemoryBudget {
+        hash: HashValue,
+    },
+    Memory {
+        usage: MemoryUsage,
+    },
+    Rehashed {
+        count: usize,
+        function: &'static str,
+    },
+    Reloaded {
+        path: String,
+        stats: IngestStats,
+        // Records swapped out.
+        replaced: usize,
+    },
+    ReloadFailed {
+        path: String,
+        error: String,
+    },
+    // Whatever a custom command chose to print.
+    Custom {
+        text: String,
+    },
+    Restored {
+        record: HashRecord,
+    },
+    NothingToUndo,
+    UndoFailed {
+        record: HashRecord,
+        reason: String,
+    },
+    RecentlyDeleted {
+        records: Vec<HashRecord>,
+    },
+    Updated {
+        old_record: HashRecord,
+        new_record: HashRecord,
+    },
+    UpdateNotFound {
+        hash: HashValue,
+    },
+    UpdateConflict {
+        expected: Money,
+        actual: HashRecord,
+    },
+    Adjusted {
+        old_record: HashRecord,
+        new_record: HashRecord,
+    },
+    AdjustNotFound {
+        hash: HashValue,
+    },
+    AdjustRejected {
+        hash: HashValue,
+        error: MoneyError,
+    },
+    Raised {
+        count: usize,
+    },
+    Found {
+        record: HashRecord,
+    },
+    SearchNotFound {
+        name: String,
+    },
+    Inspected {
+        record: HashRecord,
+        meta: RecordMeta,
+    },
+    Table {
+        records: Vec<HashRecord>,
+    },
+    Keys {
+        keys: Vec<String>,
+    },
+    // One bound per currency.
+    MinMax {
+        count: usize,
+        min: Vec<Money>,
+        max: Vec<Money>,
+    },
+    Report {
+        histogram: SalaryHistogram,
+    },
+    Similar {
+        keys: SimilarKeys,
+    },
+    Query {
+        records: Vec<HashRecord>,
+    },
+    // None when there is no view by that name.
+    View {
+        name: String,
+        rows: Option<Vec<String>>,
+    },
+    Explained {
+        command: String,
+        explanation: Explanation,
+    },
+    Skipped {
+        hash: HashValue,
+        name: String,
+    },
+    ConstraintViolation {
+        hash: HashValue,
+        constraint: String,
+    },
+    Vetoed {
+        hash: HashValue,
+        reason: String,
+    },
+}
+
+impl fmt::Display for CommandOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandOutcome::Inserted { record } => write!(f, "Inserted {}", record),
+            CommandOutcome::Duplicate { hash, name } => {
+                write!(f, "Duplicate entry for {},{}", hash, name)
+            }
+            CommandOutcome::Merged {
+                old_record,
+                new_record,
+            } => write!(
+                f,
+                "Merged record {} from {} to {}",
+                old_record.hash, old_record, new_record
+            ),
+            CommandOutcome::Deleted { record } => write!(f, "Deleted record for {}", record),
+            CommandOutcome::DeleteNotFound { name } => write!(f, "{} not found", name),
+            CommandOutcome::PopEmpty => write!(f, "Nothing to pop. Table is empty."),
+            CommandOutcome::Custom { text } => write!(f, "{}", text),
+            CommandOutcome::OutOfMemoryBudget { hash } => {
+                write!(f, "Insert rejected for {}: memory limit reached", hash)
+            }
+            CommandOutcome::Memory { usage } => write!(f, "Memory: {}", usage),
+            CommandOutcome::Rehashed { count, function } => {
+                write!(f, "Rehashed {} records to {}.", count, function)
+            }
+            CommandOutcome::Reloaded {
+                path,
+                stats,
+                replaced,
+            } => write!(
+                f,
+                "Reloaded from {}: {}; replaced {} records.",
+                path, stats, replaced
+            ),
+            CommandOutcome::ReloadFailed { path, error } => {
+                write!(f, "Failed to reload from {}: {}.", path, error)
+            }
+
+            CommandOutcome::Restored { record } => write!(f, "Restored {}", record),
+            CommandOutcome::NothingToUndo => write!(f, "Nothing to undo."),
+            CommandOutcome::UndoFailed { record, reason } => {
+                write!(f, "Undo failed for {}: {}.", record, reason)
+            }
+            CommandOutcome::RecentlyDeleted { records } => {
+                write!(f, "Recently deleted:")?;
+                for record in records {
+                    write!(f, "\n{}", record)?;
+                }
+                Ok(())
+            }
+
+            CommandOutcome::Updated {
+                old_record,
+                new_record,
+            } => write!(
+                f,
+                "Updated record {} from {} to {}",
+                old_record.hash, old_record, new_record
+            ),
+            CommandOutcome::UpdateNotFound { hash } => {
+                write!(f, "Update failed. Entry {} not found.", hash)
+            }
+            CommandOutcome::UpdateConflict { expected, actual } => write!(
+                f,
+                "Update failed. Record {} has salary {}, not {}.",
+                actual.hash, actual.salary, expected
+            ),
+            CommandOutcome::Adjusted {
+                old_record,
+                new_record,
+            } => write!(
+                f,
+                "Adjusted record {} from {} to {}",
+                old_record.hash, old_record, new_record
+            ),
+            CommandOutcome::AdjustNotFound { hash } => {
+                write!(f, "Adjust failed. Entry {} not found.", hash)
+            }
+            CommandOutcome::AdjustRejected { hash, error } => {
+                write!(f, "Adjust failed for {}: {}.", hash, error)
+            }
+            CommandOutcome::Raised { count } => write!(f, "Raised {} salaries", count),
+            CommandOutcome::Found { record } => write!(f, "Found: {}", record),
+            CommandOutcome::SearchNotFound { name } => write!(f, "{} not found.", name),
+            CommandOutcome::Inspected { record, meta } => {
+                write!(
+                    f,
+                    "Record {}: created {}, updated {}",
+                    record, meta.created_at, meta.updated_at
+                )?;
+                match meta.access_count {
+                    Some(count) => write!(f, ", accessed {} times", count),
+                    None => write!(f, ", accesses not tracked"),
+                }
+            }
+            CommandOutcome::Table { records } => {
+                write!(f, "Current Database:")?;
+                for record in records {
+                    write!(f, "\n{}", record)?;
+                }
+                Ok(())
+            }
+            CommandOutcome::Keys { keys } => {
+                write!(f, "Keys:")?;
+                for key in keys {
+                    write!(f, "\n{}", key)?;
+                }
+                Ok(())
+            }
+            CommandOutcome::MinMax { count, min, max } => {
+                let join = |amounts: &[Money]| {
+                    let amounts: Vec<String> = amounts.iter().map(|m| m.to_string()).collect();
+                    amounts.join(" | ")
+                };
+                match count {
+                    0 => write!(f, "No salaries."),
+                    _ => write!(
+                        f,
+                        "{} salaries, min {}, max {}",
+                        count,
+                        join(min),
+                        join(max)
+                    ),
+                }
+            }
+            CommandOutcome::Report { histogram } => write!(f, "{}", histogram),
+            CommandOutcome::Similar { keys } => write!(f, "{}", keys),
+            CommandOutcome::Query { records } => {
+                write!(f, "Query matched {}:", records.len())?;
+                for record in records {
+                    write!(f, "\n{}", record)?;
+                }
+                Ok(())
+            }
+            CommandOutcome::View { name, rows: None } => write!(f, "View {} not found.", name),
+            CommandOutcome::View {
+                name,
+                rows: Some(rows),
+            } => {
+                write!(f, "View {}:", name)?;
+                for row in rows {
+                    write!(f, "\n{}", row)?;
+                }
+                Ok(())
+            }
+            CommandOutcome::Explained {
+                command,
+                explanation: e,
+            } => {
+                write!(f, "Explain {}:", command)?;
+                if let Some(hash) = e.hash {
+                    write!(f, " hash {},", hash)?;
+                }
+                match e.bucket {
+                    Some(bucket) => write!(f, " bucket {} of {},", bucket, e.buckets)?,
+                    None => write!(f, " {} buckets,", e.buckets)?,
+                }
+                match e.stripe {
+                    Some(stripe) => write!(f, " stripe {} of {},", stripe, e.stripes)?,
+                    None => write!(f, " {} stripes,", e.stripes)?,
+                }
+                write!(f, " chain length {}", e.chain_length)?;
+                match (e.hash, e.position) {
+                    (Some(_), Some(position)) => write!(f, ", key at position {}", position)?,
+                    (Some(_), None) => write!(f, ", key absent")?,
+                    (None, _) => {}
+                }
+                match &e.lock {
+                    Some((LockType::Read, lock)) => write!(f, ", takes {} read lock", lock),
+                    Some((LockType::Write, lock)) => write!(f, ", takes {} write lock", lock),
+                    None => write!(f, ", takes no table lock"),
+                }
+            }
+            CommandOutcome::Skipped { hash, name } => {
+                write!(f, "Condition not met for {},{}. Skipped.", hash, name)
+            }
+            CommandOutcome::ConstraintViolation { hash, constraint } => {
+                write!(f, "Rejected {}: violates {}.", hash, constraint)
+            }
+            CommandOutcome::Vetoed { hash, reason } => write!(f, "Vetoed {}: {}.", hash, reason),
+        }
+    }
+}
+
+// The order outcomes reach stdout in, from --output-order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputOrder {
+    // As soon as each command produces them.
+    #[default]
+    Completion,
+    // Grouped per command, in the order commands were handed to the scheduler.
+    Submission,
+}
+
+impl OutputOrder {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "completion" => Some(OutputOrder::Completion),
+            "submission" => Some(OutputOrder::Submission),
+            _ => None,
+        }
+    }
+}
+
+enum Message {
+    Outcome { seq: usize, outcome: CommandOutcome },
+    // Command `seq` will not produce anything more.
+    Done { seq: usize },
+}
+
+// Cloneable handle worker threads report through.
+#[derive(Clone)]
+pub struct OutcomeSender {
+    sender: Sender<Message>,
+}
+
+impl OutcomeSender {
+    pub fn send(&self, seq: usize, outcome: CommandOutcome) {
+        let _ = self.sender.send(Message::Outcome { seq, outcome });
+    }
+
+    pub fn done(&self, seq: usize) {
+        let _ = self.sender.send(Message::Done { seq });
+    }
+}
+
+// Renders outcomes on a single thread so lines never interleave.
+pub struct OutputCollector {
+    sender: OutcomeSender,
+    handle: JoinHandle<()>,
+}
+
+impl OutputCollector {
+    pub fn new(order: OutputOrder) -> Self {
+        Self::with_writer(order, io::stdout())
+    }
+
+    pub fn with_writer(order: OutputOrder, mut out: impl Write + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || match order {
+            OutputOrder::Completion => render_completion(receiver, &mut out),
+            OutputOrder::Submission => render_submission(receiver, &mut out),
+        });
+        OutputCollector {
+            sender: OutcomeSender { sender },
+            handle,
+        }
+    }
+
+    pub fn sender(&self) -> OutcomeSender {
+        self.sender.clone()
+    }
+
+    // Waits until everything sent so far is printed. Every other sender must
+    // have been dropped first.
+    pub fn finish(self) {
+        drop(self.sender);
+        self.handle.join().unwrap();
+    }
+}
+
+fn render_completion(receiver: Receiver<Message>, out: &mut impl Write) {
+    for message in receiver {
+        if let Message::Outcome { outcome, .. } = message {
+            let _ = writeln!(out, "{}", outcome);
+        }
+    }
+}
+
+// Holds outcomes back until every earlier command is done.
+fn render_submission(receiver: Receiver<Message>, out: &mut impl Write) {
+    let mut next = 0;
+    let mut pending: HashMap<usize, Vec<CommandOutcome>> = HashMap::new();
+    let mut done = HashSet::new();
+
+    for message in receiver {
+        match message {
+            Message::Outcome { seq, outcome } if seq == next => {
+                let _ = writeln!(out, "{}", outcome);
+            }
+            Message::Outcome { seq, outcome } => pending.entry(seq).or_default().push(outcome),
+            Message::Done { seq } => {
+                done.insert(seq);
+            }
+        }
+        while done.remove(&next) {
+            next += 1;
+            for outcome in pending.remove(&next).into_iter().flatten() {
+                let _ = writeln!(out, "{}", outcome);
+            }
+        }
+    }
+
+    // A command that never reported done still gets its output printed.
+    let mut rest: Vec<_> = pending.into_iter().collect();
+    rest.sort_by_key(|(seq, _)| *seq);
+    for outcome in rest.into_iter().flat_map(|(_, outcomes)| outcomes) {
+        let _ = writeln!(out, "{}", outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_submission, CommandOutcome, Message};
+    use crate::hash::HashWidth;
+    use crate::hash_table::{HashTableConfig, SalaryTable};
+    use crate::logger::LockType;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_submission_order() {
+        let (sender, receiver) = mpsc::channel();
+        let missing = |seq: usize| Message::Outcome {
+            seq,
+            outcome: CommandOutcome::SearchNotFound {
+                name: seq.to_string(),
+            },
+        };
+        for message in [
+            missing(2),
+            missing(1),
+            Message::Done { seq: 1 },
+            missing(0),
+            Message::Done { seq: 0 },
+            Message::Done { seq: 2 },
+        ] {
+            sender.send(message).unwrap();
+        }
+        drop(sender);
+
+        let mut out = Vec::new();
+        render_submission(receiver, &mut out);
+        assert_eq!(
+            "0 not found.\n1 not found.\n2 not found.\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut table = SalaryTable::new();
+        table.set_config(HashTableConfig {
+            max_load_factor: None,
+            ..HashTableConfig::default()
+        });
+        table.set_stripe_count(2);
+        table.set_bucket_count(8);
+        let keys: Vec<String> = (0..20).map(|i| format!("k{}", i)).collect();
+        for key in keys.iter() {
+            table.insert(key, "1".parse().unwrap(), 0);
+        }
+
+        // Worked out as the table does: a stripe by 64-bit hash, then a chain
+        // within it by the table's hash, newest record first.
+        let place = |key: &str| {
+            let stripe = (HashWidth::Bits64.hash(key.as_bytes()) % 2) as usize;
+            let chain = (HashWidth::Bits32.hash(key.as_bytes()) % 4) as usize;
+            (stripe, stripe * 4 + chain)
+        };
+        let chain_of = |key: &str| -> Vec<&String> {
+            keys.iter().filter(|k| place(k) == place(key)).collect()
+        };
+        // The first key sharing its chain, so others are ahead of it.
+        let key = keys.iter().find(|k| chain_of(k).len() > 1).unwrap();
+        let (stripe, bucket) = place(key);
+        let chain = chain_of(key);
+
+        let outcome = CommandOutcome::Explained {
+            command: format!("search,{}", key),
+            explanation: table.explain(Some(key.as_str()), Some(LockType::Read), 0),
+        };
+        assert_eq!(
+            format!(
+                "Explain search,{key}: hash {}, bucket {} of 8, stripe {} of 2, \
+                 chain length {}, key at position {}, takes table stripe {} read lock",
+                SalaryTable::jenkins_one_at_a_time_hash(key.as_bytes()),
+                bucket,
+                stripe,
+                chain.len(),
+                chain.len() - 1,
+                stripe,
+                key = key
+            ),
+            outcome.to_string()
+        );
+    }
+}
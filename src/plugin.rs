@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use crate::hash_table::SalaryTable;
+use crate::logger::LockType;
+use crate::output::CommandOutcome;
+
+// What a custom command gets besides the table: the fields between the
+// command name and the priority, trimmed, and the priority itself.
+pub struct CommandContext<'a> {
+    pub args: &'a [String],
+    pub priority: u32,
+}
+
+// Runs a custom command. None prints nothing.
+pub type Handler = fn(&SalaryTable, &CommandContext) -> Option<CommandOutcome>;
+
+#[derive(Clone, Copy)]
+pub struct CustomCommand {
+    pub handler: Handler,
+    // The table lock the handler takes, for `explain`.
+    pub lock: Option<LockType>,
+}
+
+// Commands added to the command language without touching the parser. Names
+// are only looked up when no built-in command matches, so a built-in always
+// wins.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: HashMap<String, CustomCommand>,
+}
+
+impl CommandRegistry {
+    // Replaces any command already registered under `name`.
+    pub fn register_command(&mut self, name: &str, lock: Option<LockType>, handler: Handler) {
+        self.commands
+            .insert(name.to_string(), CustomCommand { handler, lock });
+    }
+
+    pub fn get(&self, name: &str) -> Option<CustomCommand> {
+        self.commands.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandContext, CommandRegistry};
+    use crate::hash_table::SalaryTable;
+    use crate::logger::{LockType, ThreadLogger};
+    use crate::output::CommandOutcome;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_register_command() {
+        let mut registry = CommandRegistry::default();
+        registry.register_command("count", Some(LockType::Read), |table, context| {
+            let count = table.select_where(|_| true, context.priority).len();
+            Some(CommandOutcome::Custom {
+                text: format!("{} {}", context.args.join(","), count),
+            })
+        });
+        assert!(registry.get("insert").is_none());
+
+        let path = std::env::temp_dir().join("cht_test_register_command.log");
+        let table = SalaryTable::with_logger(Arc::new(ThreadLogger::new(path.to_str().unwrap())));
+        table.insert("a", "1".parse().unwrap(), 0);
+        let command = registry.get("count").unwrap();
+        let context = CommandContext {
+            args: &["records".to_string()],
+            priority: 0,
+        };
+        match (command.handler)(&table, &context) {
+            Some(CommandOutcome::Custom { text }) => assert_eq!("records 1", text),
+            _ => panic!("expected custom output"),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Nested includes deeper than this are treated as a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    Io { path: PathBuf, message: String },
+    IncludeDepth { path: PathBuf },
+    UnknownVariable { name: String, line: String },
+    UnclosedRepeat { path: PathBuf },
+    UnexpectedClose { path: PathBuf },
+    InvalidDirective { line: String },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::Io { path, message } => {
+                write!(f, "cannot read {}: {}", path.display(), message)
+            }
+            PreprocessError::IncludeDepth { path } => write!(
+                f,
+                "include depth exceeded at {} (include cycle?)",
+                path.display()
+            ),
+            PreprocessError::UnknownVariable { name, line } => {
+                write!(f, "unknown variable ${} in: {}", name, line)
+            }
+            PreprocessError::UnclosedRepeat { path } => {
+                write!(f, "repeat block not closed in {}", path.display())
+            }
+            PreprocessError::UnexpectedClose { path } => {
+                write!(f, "'}}' without a matching repeat in {}", path.display())
+            }
+            PreprocessError::InvalidDirective { line } => write!(f, "invalid directive: {}", line),
+        }
+    }
+}
+
+// Expands a command file into plain command lines before they are parsed:
+//
+//   include other.txt      splice in another file (relative to this one)
+//   set name value         define $name for the lines that follow
+//   repeat 3 {             repeat the enclosed lines; $i is the iteration
+//   }
+//
+// `$seq` expands to the number of command lines emitted so far, which gives
+// repeated commands distinct priorities.
+pub fn preprocess(path: &Path) -> Result<Vec<String>, PreprocessError> {
+    let mut expander = Expander {
+        vars: HashMap::new(),
+        output: Vec::new(),
+    };
+    expander.expand_file(path, 0)?;
+    Ok(expander.output)
+}
+
+struct Expander {
+    vars: HashMap<String, String>,
+    output: Vec<String>,
+}
+
+impl Expander {
+    fn expand_file(&mut self, path: &Path, depth: usize) -> Result<(), PreprocessError> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(PreprocessError::IncludeDepth {
+                path: path.to_path_buf(),
+            });
+        }
+
+        let contents = fs::read_to_string(path).map_err(|e| PreprocessError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        let lines: Vec<&str> = contents.lines().collect();
+
+        let mut pos = 0;
+        self.expand_lines(&lines, &mut pos, path, depth, false)
+    }
+
+    // Expands lines[..] from `pos` until the end, or until the closing '}' when
+    // inside a repeat block.
+    fn expand_lines(
+        &mut self,
+        lines: &[&str],
+        pos: &mut usize,
+        path: &Path,
+        depth: usize,
+        in_block: bool,
+    ) -> Result<(), PreprocessError> {
+        while *pos < lines.len() {
+            let line = lines[*pos].trim();
+            *pos += 1;
+
+            if line == "}" {
+                if in_block {
+                    return Ok(());
+                }
+                return Err(PreprocessError::UnexpectedClose {
+                    path: path.to_path_buf(),
+                });
+            }
+
+            if let Some(rest) = line.strip_prefix("include ") {
+                let target = self.substitute(rest.trim())?;
+                let base = path.parent().unwrap_or(Path::new("."));
+                self.expand_file(&base.join(target), depth + 1)?;
+            } else if let Some(rest) = line.strip_prefix("set ") {
+                let (name, value) = rest.trim().split_once(' ').ok_or_else(|| {
+                    PreprocessError::InvalidDirective {
+                        line: line.to_string(),
+                    }
+                })?;
+                let value = self.substitute(value.trim())?;
+                self.vars.insert(name.to_string(), value);
+            } else if let Some(rest) = line.strip_prefix("repeat ") {
+                let count = rest
+                    .trim()
+                    .strip_suffix('{')
+                    .and_then(|n| self.substitute(n.trim()).ok())
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .ok_or_else(|| PreprocessError::InvalidDirective {
+                        line: line.to_string(),
+                    })?;
+
+                let body_start = *pos;
+                let outer_i = self.vars.get("i").cloned();
+                for i in 0..count {
+                    *pos = body_start;
+                    self.vars.insert("i".to_string(), i.to_string());
+                    self.expand_lines(lines, pos, path, depth, true)?;
+                }
+                if count == 0 {
+                    // Still consume the body so it is skipped.
+                    let emitted = self.output.len();
+                    self.expand_lines(lines, pos, path, depth, true)?;
+                    self.output.truncate(emitted);
+                }
+                match outer_i {
+                    Some(i) => self.vars.insert("i".to_string(), i),
+                    None => self.vars.remove("i"),
+                };
+            } else if !line.is_empty() {
+                let expanded = self.substitute(line)?;
+                self.output.push(expanded);
+            }
+        }
+
+        if in_block {
+            return Err(PreprocessError::UnclosedRepeat {
+                path: path.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+
+    fn substitute(&self, line: &str) -> Result<String, PreprocessError> {
+        let mut result = String::with_capacity(line.len());
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((_, c)) = chars.next() {
+            if c != '$' {
+                result.push(c);
+                continue;
+            }
+
+            let mut name = String::new();
+            while let Some(&(_, next)) = chars.peek() {
+                if next.is_ascii_alphanumeric() || next == '_' {
+                    name.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            if name.is_empty() {
+                result.push('$');
+            } else if name == "seq" {
+                result.push_str(&self.output.len().to_string());
+            } else if let Some(value) = self.vars.get(&name) {
+                result.push_str(value);
+            } else {
+                return Err(PreprocessError::UnknownVariable {
+                    name,
+                    line: line.to_string(),
+                });
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::preprocess;
+    use std::fs;
+
+    #[test]
+    fn test_preprocess() {
+        let dir = std::env::temp_dir().join("cht_test_preprocess");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("inner.txt"), "search,$who,0,$seq\n").unwrap();
+        fs::write(
+            dir.join("main.txt"),
+            "set who Link\nrepeat 2 {\ninsert,$who$i,1,$seq\n}\ninclude inner.txt\n",
+        )
+        .unwrap();
+
+        let lines = preprocess(&dir.join("main.txt")).unwrap();
+        assert_eq!(
+            vec!["insert,Link0,1,0", "insert,Link1,1,1", "search,Link,0,2"],
+            lines
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+use crate::logger::LockType;
+
+#[derive(Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    // (priority, ticket, write): lowest priority number first, then arrival.
+    waiting: BTreeSet<(u32, u64, bool)>,
+    next_ticket: u64,
+}
+
+// Admits table lock requests in command priority order (lowest number first,
+// ties by arrival) instead of whichever waiter the OS happens to wake. A
+// request goes in once it is first in line and fits the current holders, so
+// readers at the front enter together and a writer at the front waits for
+// them to leave while holding back everyone behind it.
+#[derive(Default)]
+pub struct PriorityGate {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+// A place inside the gate, given up on drop.
+pub struct Admission<'a> {
+    gate: &'a PriorityGate,
+    lock_type: LockType,
+}
+
+impl PriorityGate {
+    pub fn enter(&self, priority: u32, lock_type: LockType) -> Admission<'_> {
+        let write = lock_type == LockType::Write;
+        let mut state = self.state.lock().unwrap();
+        let me = (priority, state.next_ticket, write);
+        state.next_ticket += 1;
+        state.waiting.insert(me);
+        let mut state = self
+            .changed
+            .wait_while(state, |s| {
+                s.waiting.first() != Some(&me) || s.writer || (write && s.readers > 0)
+            })
+            .unwrap();
+        state.waiting.remove(&me);
+        match lock_type {
+            LockType::Read => state.readers += 1,
+            LockType::Write => state.writer = true,
+        }
+        drop(state);
+        // The next in line may be a reader that can join.
+        self.changed.notify_all();
+        Admission {
+            gate: self,
+            lock_type,
+        }
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().unwrap();
+        match self.lock_type {
+            LockType::Read => state.readers -= 1,
+            LockType::Write => state.writer = false,
+        }
+        drop(state);
+        self.gate.changed.notify_all();
+    }
+}
+
+// A table lock guard holding its place in the gate, if there is one. The
+// guard is declared first so the lock is released before the next in line is
+// let in.
+pub struct Gated<'a, G> {
+    guard: G,
+    _admission: Option<Admission<'a>>,
+}
+
+impl<'a, G> Gated<'a, G> {
+    pub fn new(guard: G, admission: Option<Admission<'a>>) -> Self {
+        Gated {
+            guard,
+            _admission: admission,
+        }
+    }
+}
+
+impl<G: Deref> Deref for Gated<'_, G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Gated<'_, G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityGate;
+    use crate::logger::LockType;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_priority_order() {
+        let gate = PriorityGate::default();
+        let order = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            let held = gate.enter(0, LockType::Write);
+            for priority in [5, 1, 3] {
+                let (gate, order) = (&gate, &order);
+                scope.spawn(move || {
+                    let _admission = gate.enter(priority, LockType::Write);
+                    order.lock().unwrap().push(priority);
+                });
+            }
+            // Let all three queue up behind the holder.
+            thread::sleep(Duration::from_millis(100));
+            drop(held);
+        });
+        assert_eq!(vec![1, 3, 5], order.into_inner().unwrap());
+
+        // Readers share the gate.
+        let first = gate.enter(0, LockType::Read);
+        let second = gate.enter(1, LockType::Read);
+        drop((first, second));
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt;
+
+use crate::hash::HashValue;
+use crate::hash_table::HashRecord;
+use crate::money::Money;
+
+// A parsed filter such as `salary > 50000 && name ~ "^J"`.
+//
+//   expr       := and ("||" and)*
+//   and        := unary ("&&" unary)*
+//   unary      := "!" unary | "(" expr ")" | comparison
+//   comparison := field op value
+//   field      := name | salary | hash
+//   op         := == | != | < | <= | > | >= | ~   (~ only for name)
+//   value      := number | "string"
+//
+// `~` matches a small regex subset: literals, `.`, `*`, `^` and `$`.
+#[derive(Debug, Clone)]
+pub enum Query {
+    Or(Box<Query>, Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Name(Op, String),
+    Salary(Op, Money),
+    Hash(Op, HashValue),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Matches,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueryError {
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn error<T>(message: impl Into<String>) -> Result<T, QueryError> {
+    Err(QueryError {
+        message: message.into(),
+    })
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(token) => error(format!("unexpected {:?}", token)),
+        }
+    }
+
+    pub fn matches(&self, record: &HashRecord) -> bool {
+        match self {
+            Query::Or(a, b) => a.matches(record) || b.matches(record),
+            Query::And(a, b) => a.matches(record) && b.matches(record),
+            Query::Not(q) => !q.matches(record),
+            Query::Name(Op::Matches, pattern) => regex_match(pattern, &record.name),
+            Query::Name(op, value) => compare(*op, record.name.as_str().partial_cmp(value)),
+            Query::Salary(op, value) => compare(*op, record.salary.partial_cmp(value)),
+            Query::Hash(op, value) => compare(*op, record.hash.partial_cmp(value)),
+        }
+    }
+}
+
+// Unordered values (salaries in different currencies) only satisfy `!=`.
+fn compare(op: Op, ordering: Option<std::cmp::Ordering>) -> bool {
+    use std::cmp::Ordering::*;
+    match (op, ordering) {
+        (Op::Ne, None) => true,
+        (_, None) => false,
+        (Op::Eq, Some(o)) => o == Equal,
+        (Op::Ne, Some(o)) => o != Equal,
+        (Op::Lt, Some(o)) => o == Less,
+        (Op::Le, Some(o)) => o != Greater,
+        (Op::Gt, Some(o)) => o == Greater,
+        (Op::Ge, Some(o)) => o != Less,
+        (Op::Matches, Some(_)) => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            ' ' | '\t' => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '~' => Token::Op(Op::Matches),
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => s.extend(chars.next()),
+                        Some(c) => s.push(c),
+                        None => return error("unterminated string"),
+                    }
+                }
+                Token::Str(s)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut s = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    s.push(c);
+                }
+                Token::Number(s)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut s = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    s.push(c);
+                }
+                Token::Ident(s)
+            }
+            // Including a lone `&`, `|` or `=`.
+            c => return error(format!("unexpected character '{}'", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.and()?;
+        while self.eat(&Token::Or) {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.unary()?;
+        while self.eat(&Token::And) {
+            query = Query::And(Box::new(query), Box::new(self.unary()?));
+        }
+        Ok(query)
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryError> {
+        if self.eat(&Token::Not) {
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+        if self.eat(&Token::Open) {
+            let query = self.or()?;
+            if !self.eat(&Token::Close) {
+                return error("missing ')'");
+            }
+            return Ok(query);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Query, QueryError> {
+        let field = match self.next() {
+            Some(Token::Ident(field)) => field,
+            other => return error(format!("expected a field, found {:?}", other)),
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            other => return error(format!("expected an operator, found {:?}", other)),
+        };
+        let value = match self.next() {
+            Some(Token::Number(v)) | Some(Token::Str(v)) => v,
+            other => return error(format!("expected a value, found {:?}", other)),
+        };
+
+        if op == Op::Matches && field != "name" {
+            return error("~ only applies to name");
+        }
+        match field.as_str() {
+            "name" => Ok(Query::Name(op, value)),
+            "salary" => match value.parse() {
+                Ok(salary) => Ok(Query::Salary(op, salary)),
+                Err(e) => error(e.to_string()),
+            },
+            "hash" => match value.parse() {
+                Ok(hash) => Ok(Query::Hash(op, hash)),
+                Err(_) => error(format!("invalid hash: {}", value)),
+            },
+            _ => error(format!("unknown field: {}", field)),
+        }
+    }
+}
+
+// Whether `pattern` matches anywhere in `text`.
+fn regex_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    if let Some(rest) = pattern.strip_prefix(&['^']) {
+        return match_here(rest, &text);
+    }
+    (0..=text.len()).any(|start| match_here(&pattern, &text[start..]))
+}
+
+fn match_here(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => true,
+        ['$'] => text.is_empty(),
+        [c, '*', rest @ ..] => {
+            // Try the shortest run first, then one more `c` at a time.
+            let mut i = 0;
+            loop {
+                if match_here(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && (*c == '.' || text[i] == *c) {
+                    i += 1;
+                } else {
+                    return false;
+                }
+            }
+        }
+        [c, rest @ ..] => {
+            !text.is_empty() && (*c == '.' || text[0] == *c) && match_here(rest, &text[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+    use crate::hash_table::HashRecord;
+
+    fn record(name: &str, salary: &str) -> HashRecord {
+        HashRecord {
+            hash: 7,
+            name: name.to_string(),
+            salary: salary.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let query = Query::parse(r#"salary > 50000 && name ~ "^J""#).unwrap();
+        assert!(query.matches(&record("Jill", "60000")));
+        assert!(!query.matches(&record("Jill", "40000")));
+        assert!(!query.matches(&record("Bill", "60000")));
+
+        let query = Query::parse(r#"!(name == "Bob" || hash != 7) && salary <= "10 EUR""#).unwrap();
+        assert!(query.matches(&record("Ann", "10 EUR")));
+        assert!(!query.matches(&record("Ann", "10")));
+        assert!(!query.matches(&record("Bob", "1 EUR")));
+
+        let query = Query::parse(r#"name ~ "a.*e$""#).unwrap();
+        assert!(query.matches(&record("Jane", "1")));
+        assert!(!query.matches(&record("Janet", "1")));
+
+        assert!(Query::parse("salary ~ 5").is_err());
+        assert!(Query::parse("salary > 5 &&").is_err());
+        assert!(Query::parse("age > 5").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+
+use crate::hash::HashValue;
+use crate::hash_table::HashRecord;
+
+// How names and salaries are written to a sink. Each sink (hash.log, a CDC
+// file) carries its own policy; stdout is never redacted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Redaction {
+    #[default]
+    None,
+    // Names are replaced by "#<hash in hex>" so lines for one key still match up.
+    HashKeys,
+    // Salaries are replaced by "***".
+    MaskValues,
+    // Only the hash is kept.
+    DropPayloads,
+}
+
+impl Redaction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(Redaction::None),
+            "hash-keys" => Some(Redaction::HashKeys),
+            "mask-values" => Some(Redaction::MaskValues),
+            "drop" => Some(Redaction::DropPayloads),
+            _ => None,
+        }
+    }
+
+    pub fn key(&self, hash: HashValue, key: &(impl fmt::Display + ?Sized)) -> Option<String> {
+        match self {
+            Redaction::None | Redaction::MaskValues => Some(key.to_string()),
+            Redaction::HashKeys => Some(format!("#{:08x}", hash)),
+            Redaction::DropPayloads => None,
+        }
+    }
+
+    pub fn value(&self, value: &(impl fmt::Display + ?Sized)) -> Option<String> {
+        match self {
+            Redaction::None | Redaction::HashKeys => Some(value.to_string()),
+            Redaction::MaskValues => Some("***".to_string()),
+            Redaction::DropPayloads => None,
+        }
+    }
+
+    // "<hash>,<key>[,<value>]" with the policy applied; dropped fields are omitted.
+    pub fn fields(
+        &self,
+        hash: HashValue,
+        key: &(impl fmt::Display + ?Sized),
+        value: Option<&dyn fmt::Display>,
+    ) -> String {
+        let mut fields = vec![hash.to_string()];
+        fields.extend(self.key(hash, key));
+        fields.extend(value.and_then(|v| self.value(v)));
+        fields.join(",")
+    }
+
+    pub fn record<K: fmt::Display, V: fmt::Display>(&self, record: &HashRecord<K, V>) -> String {
+        self.fields(record.hash, &record.name, Some(&record.salary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redaction;
+
+    #[test]
+    fn test_redaction_fields() {
+        let cases = vec![
+            (Redaction::None, "42,Link,82000"),
+            (Redaction::HashKeys, "42,#0000002a,82000"),
+            (Redaction::MaskValues, "42,Link,***"),
+            (Redaction::DropPayloads, "42"),
+        ];
+
+        let salary: crate::money::Money = "82000".parse().unwrap();
+        for (policy, expected) in cases {
+            assert_eq!(expected, policy.fields(42, "Link", Some(&salary)));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use crate::cdc::Change;
+use crate::hash_table::HashRecord;
+
+// Rebuilds a run's table from its --cdc file one change at a time, to find
+// the step where a wrong final table went wrong. Each change is checked
+// against the table so far: one that expects a record that is not there, or
+// a different salary, means changes were lost or published out of order.
+#[derive(Default)]
+pub struct Replay {
+    records: HashMap<String, HashRecord>,
+}
+
+impl Replay {
+    // What about `change` did not fit the table, if anything. It is applied
+    // either way.
+    pub fn apply(&mut self, change: &Change) -> Option<String> {
+        match change {
+            Change::Insert { record } => self
+                .records
+                .insert(record.name.clone(), record.clone())
+                .map(|old| format!("{} was already in the table as {}", record.name, old)),
+            Change::Update {
+                old_record,
+                new_record,
+            } => match self
+                .records
+                .insert(new_record.name.clone(), new_record.clone())
+            {
+                None => Some(format!("{} was not in the table", new_record.name)),
+                Some(old) if old.salary != old_record.salary => Some(format!(
+                    "{} had salary {}, not {}",
+                    old.name, old.salary, old_record.salary
+                )),
+                Some(_) => None,
+            },
+            Change::Delete { record } => match self.records.remove(&record.name) {
+                None => Some(format!("{} was not in the table", record.name)),
+                Some(_) => None,
+            },
+        }
+    }
+
+    // In hash order, like the final table.
+    pub fn records(&self) -> Vec<&HashRecord> {
+        let mut records: Vec<&HashRecord> = self.records.values().collect();
+        records.sort_by(|a, b| a.hash.cmp(&b.hash).then_with(|| a.name.cmp(&b.name)));
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Replay;
+    use crate::cdc::ChangeEvent;
+
+    #[test]
+    fn test_replay() {
+        let lines = [
+            "INSERT,2,b,20",
+            "INSERT,1,a,10 [cid=7]",
+            "UPDATE,1,a,15,10",
+            "DELETE,2,b,20",
+            "UPDATE,2,b,30,20",
+        ];
+        let mut replay = Replay::default();
+        let problems: Vec<Option<String>> = lines
+            .iter()
+            .map(|line| replay.apply(&ChangeEvent::parse(line).unwrap().change))
+            .collect();
+        assert_eq!(
+            Some(7),
+            ChangeEvent::parse(lines[1]).unwrap().correlation_id
+        );
+        assert_eq!(vec![None, None, None, None], problems[..4]);
+        assert_eq!(Some("b was not in the table".to_string()), problems[4]);
+
+        let records: Vec<String> = replay.records().iter().map(|r| r.to_string()).collect();
+        assert_eq!(vec!["1,a,15", "2,b,30"], records);
+        assert!(ChangeEvent::parse("INSERT,1,a,***").is_none());
+    }
+}
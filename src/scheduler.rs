@@ -0,0 +1,478 @@
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// What to do with commands that share a priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TiePolicy {
+    // One after another, in command-file order.
+    #[default]
+    FileOrder,
+    // All at once: tied commands share a turn.
+    Concurrent,
+    // One after another, shuffled reproducibly from the seed.
+    Random {
+        seed: u64,
+    },
+}
+
+impl TiePolicy {
+    // "file", "concurrent", "random" or "random:<seed>".
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "file" => Some(TiePolicy::FileOrder),
+            "concurrent" => Some(TiePolicy::Concurrent),
+            "random" => Some(TiePolicy::Random { seed: 0 }),
+            _ => {
+                let seed = s.strip_prefix("random:")?.parse().ok()?;
+                Some(TiePolicy::Random { seed })
+            }
+        }
+    }
+}
+
+// Orders items by priority and gives each a turn slot. Slots are dense from 0 so
+// gaps or duplicates in the priorities can never leave a thread waiting for a
+// turn that will not come.
+pub fn assign_turns<T>(
+    mut items: Vec<T>,
+    priority: impl Fn(&T) -> u32,
+    policy: TiePolicy,
+) -> Vec<(usize, T)> {
+    // Stable, so ties keep file order.
+    items.sort_by_key(|item| priority(item));
+
+    if let TiePolicy::Random { seed } = policy {
+        let mut rng = XorShift::new(seed);
+        let mut start = 0;
+        while start < items.len() {
+            let p = priority(&items[start]);
+            let end = start + items[start..].partition_point(|item| priority(item) == p);
+            // Fisher-Yates over the tie group.
+            for i in (start + 1..end).rev() {
+                let j = start + (rng.next() % (i - start + 1) as u64) as usize;
+                items.swap(i, j);
+            }
+            start = end;
+        }
+    }
+
+    let mut slot = 0;
+    let mut previous = None;
+    let mut turns = Vec::with_capacity(items.len());
+    for item in items {
+        let p = priority(&item);
+        if previous.is_some() && (policy != TiePolicy::Concurrent || previous != Some(p)) {
+            slot += 1;
+        }
+        previous = Some(p);
+        turns.push((slot, item));
+    }
+    turns
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift.
+        XorShift(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Decides when each command may start. Commands arrive with the turn slot
+// from assign_turns; a command calls wait_for_turn, then start once it is really
+// under way (sleep and barrier do their waiting in between), then finish.
+pub trait Scheduler: Send + Sync {
+    fn wait_for_turn(&self, slot: usize);
+    fn start(&self, slot: usize);
+    fn finish(&self, slot: usize);
+    // Blocks until at least `count` commands have finished.
+    fn wait_for_completed(&self, count: usize);
+}
+
+// Which scheduler to run with, from --scheduler.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SchedulerKind {
+    #[default]
+    Strict,
+    Bands {
+        width: usize,
+    },
+    Pool {
+        workers: usize,
+    },
+    Rate {
+        per_second: f64,
+    },
+}
+
+impl SchedulerKind {
+    // "strict", "bands:<width>", "pool:<workers>" or "rate:<per-second>".
+    pub fn parse(s: &str) -> Option<Self> {
+        if s == "strict" {
+            return Some(SchedulerKind::Strict);
+        }
+        let (name, arg) = s.split_once(':')?;
+        match name {
+            "bands" => Some(SchedulerKind::Bands {
+                width: arg.parse().ok().filter(|w| *w > 0)?,
+            }),
+            "pool" => Some(SchedulerKind::Pool {
+                workers: arg.parse().ok().filter(|w| *w > 0)?,
+            }),
+            "rate" => Some(SchedulerKind::Rate {
+                per_second: arg.parse().ok().filter(|r: &f64| *r > 0.0)?,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn build(self, slot_sizes: Vec<usize>) -> Box<dyn Scheduler> {
+        match self {
+            SchedulerKind::Strict => Box::new(StrictTurnOrder::new(slot_sizes)),
+            SchedulerKind::Bands { width } => Box::new(PriorityBands::new(&slot_sizes, width)),
+            SchedulerKind::Pool { workers } => Box::new(WorkStealingPool::new(slot_sizes, workers)),
+            SchedulerKind::Rate { per_second } => Box::new(RateLimited::new(
+                slot_sizes,
+                Duration::from_secs_f64(1.0 / per_second),
+            )),
+        }
+    }
+}
+
+// Count of finished commands, shared by every scheduler for barriers.
+struct Completion {
+    count: Mutex<usize>,
+    condvar: Condvar,
+}
+
+impl Completion {
+    fn new() -> Self {
+        Completion {
+            count: Mutex::new(0),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn add(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.condvar.notify_all();
+    }
+
+    fn wait_for(&self, count: usize) {
+        let mut completed = self.count.lock().unwrap();
+        while *completed < count {
+            completed = self.condvar.wait(completed).unwrap();
+        }
+    }
+}
+
+struct TurnState {
+    turn: usize,
+    started: usize,
+}
+
+// Hands out turns slot by slot. A slot's turn ends once every command in it has
+// started.
+pub struct StrictTurnOrder {
+    state: Mutex<TurnState>,
+    condvar: Condvar,
+    slot_sizes: Vec<usize>,
+    completed: Completion,
+}
+
+impl StrictTurnOrder {
+    pub fn new(slot_sizes: Vec<usize>) -> Self {
+        StrictTurnOrder {
+            state: Mutex::new(TurnState {
+                turn: 0,
+                started: 0,
+            }),
+            condvar: Condvar::new(),
+            slot_sizes,
+            completed: Completion::new(),
+        }
+    }
+}
+
+impl Scheduler for StrictTurnOrder {
+    fn wait_for_turn(&self, slot: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.turn != slot {
+            state = self.condvar.wait(state).unwrap();
+        }
+    }
+
+    // Passes the turn on once the last command of the slot has started.
+    fn start(&self, _slot: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.started += 1;
+        if state.started == self.slot_sizes[state.turn] {
+            state.turn += 1;
+            state.started = 0;
+            self.condvar.notify_all();
+        }
+    }
+
+    fn finish(&self, _slot: usize) {
+        self.completed.add();
+    }
+
+    fn wait_for_completed(&self, count: usize) {
+        self.completed.wait_for(count);
+    }
+}
+
+// Groups every `width` consecutive slots into a band. Everything in a band runs
+// at once, and a band only starts after the previous one has finished.
+pub struct PriorityBands {
+    width: usize,
+    // Number of commands in all earlier bands, per band.
+    band_starts: Vec<usize>,
+    completed: Completion,
+}
+
+impl PriorityBands {
+    pub fn new(slot_sizes: &[usize], width: usize) -> Self {
+        let mut band_starts = Vec::new();
+        let mut total = 0;
+        for band in slot_sizes.chunks(width) {
+            band_starts.push(total);
+            total += band.iter().sum::<usize>();
+        }
+        PriorityBands {
+            width,
+            band_starts,
+            completed: Completion::new(),
+        }
+    }
+}
+
+impl Scheduler for PriorityBands {
+    fn wait_for_turn(&self, slot: usize) {
+        self.completed.wait_for(self.band_starts[slot / self.width]);
+    }
+
+    fn start(&self, _slot: usize) {}
+
+    fn finish(&self, _slot: usize) {
+        self.completed.add();
+    }
+
+    fn wait_for_completed(&self, count: usize) {
+        self.completed.wait_for(count);
+    }
+}
+
+// Strict turn order, but with only `workers` commands in flight: whichever
+// worker frees up first takes the next command.
+pub struct WorkStealingPool {
+    turns: StrictTurnOrder,
+    workers: usize,
+    running: Mutex<usize>,
+    condvar: Condvar,
+}
+
+impl WorkStealingPool {
+    pub fn new(slot_sizes: Vec<usize>, workers: usize) -> Self {
+        WorkStealingPool {
+            turns: StrictTurnOrder::new(slot_sizes),
+            workers,
+            running: Mutex::new(0),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+impl Scheduler for WorkStealingPool {
+    fn wait_for_turn(&self, slot: usize) {
+        self.turns.wait_for_turn(slot);
+    }
+
+    fn start(&self, slot: usize) {
+        let mut running = self.running.lock().unwrap();
+        while *running == self.workers {
+            running = self.condvar.wait(running).unwrap();
+        }
+        *running += 1;
+        drop(running);
+        self.turns.start(slot);
+    }
+
+    fn finish(&self, slot: usize) {
+        *self.running.lock().unwrap() -= 1;
+        self.condvar.notify_one();
+        self.turns.finish(slot);
+    }
+
+    fn wait_for_completed(&self, count: usize) {
+        self.turns.wait_for_completed(count);
+    }
+}
+
+// Strict turn order with starts spaced at least `interval` apart.
+pub struct RateLimited {
+    turns: StrictTurnOrder,
+    interval: Duration,
+    next_start: Mutex<Option<Instant>>,
+}
+
+impl RateLimited {
+    pub fn new(slot_sizes: Vec<usize>, interval: Duration) -> Self {
+        RateLimited {
+            turns: StrictTurnOrder::new(slot_sizes),
+            interval,
+            next_start: Mutex::new(None),
+        }
+    }
+}
+
+impl Scheduler for RateLimited {
+    fn wait_for_turn(&self, slot: usize) {
+        self.turns.wait_for_turn(slot);
+    }
+
+    fn start(&self, slot: usize) {
+        // Reserve a start time, then wait for it outside the lock.
+        let now = Instant::now();
+        let at = {
+            let mut next_start = self.next_start.lock().unwrap();
+            let at = next_start.map_or(now, |next| next.max(now));
+            *next_start = Some(at + self.interval);
+            at
+        };
+        thread::sleep(at - now);
+        self.turns.start(slot);
+    }
+
+    fn finish(&self, slot: usize) {
+        self.turns.finish(slot);
+    }
+
+    fn wait_for_completed(&self, count: usize) {
+        self.turns.wait_for_completed(count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assign_turns, SchedulerKind, TiePolicy};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn test_assign_turns() {
+        let items = vec![(2, 'a'), (1, 'b'), (2, 'c'), (5, 'd')];
+
+        let file = assign_turns(items.clone(), |i| i.0, TiePolicy::FileOrder);
+        assert_eq!(
+            vec![(0, 'b'), (1, 'a'), (2, 'c'), (3, 'd')],
+            file.iter().map(|(s, i)| (*s, i.1)).collect::<Vec<_>>()
+        );
+
+        let concurrent = assign_turns(items.clone(), |i| i.0, TiePolicy::Concurrent);
+        assert_eq!(
+            vec![0, 1, 1, 2],
+            concurrent.iter().map(|(s, _)| *s).collect::<Vec<_>>()
+        );
+
+        let seeded = |seed| assign_turns(items.clone(), |i| i.0, TiePolicy::Random { seed });
+        let first: Vec<char> = seeded(7).iter().map(|(_, i)| i.1).collect();
+        let second: Vec<char> = seeded(7).iter().map(|(_, i)| i.1).collect();
+        assert_eq!(first, second);
+        assert_eq!('b', first[0]);
+        assert_eq!('d', first[3]);
+    }
+
+    // Runs one command per slot through the scheduler and returns the start
+    // order and the most commands seen in flight at once.
+    fn run(kind: SchedulerKind, slots: usize) -> (Vec<usize>, usize) {
+        let scheduler = kind.build(vec![1; slots]);
+        let order = Mutex::new(Vec::new());
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for slot in (0..slots).rev() {
+                let (scheduler, order, running, peak) = (&scheduler, &order, &running, &peak);
+                s.spawn(move || {
+                    scheduler.wait_for_turn(slot);
+                    // Still holding the turn, so pushes follow start order.
+                    order.lock().unwrap().push(slot);
+                    scheduler.start(slot);
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(std::time::Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    scheduler.finish(slot);
+                });
+            }
+        });
+        (order.into_inner().unwrap(), peak.into_inner())
+    }
+
+    // A barrier as main.rs runs one: it waits for the earlier commands while
+    // holding its turn, so nothing after it starts early either.
+    #[test]
+    fn test_barrier() {
+        for kind in [SchedulerKind::Strict, SchedulerKind::Pool { workers: 2 }] {
+            let scheduler = kind.build(vec![1; 4]);
+            let finished = AtomicUsize::new(0);
+            let (at_barrier, after_barrier) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            thread::scope(|s| {
+                let (scheduler, finished) = (&scheduler, &finished);
+                for slot in 0..2 {
+                    s.spawn(move || {
+                        scheduler.wait_for_turn(slot);
+                        scheduler.start(slot);
+                        thread::sleep(std::time::Duration::from_millis(20));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                        scheduler.finish(slot);
+                    });
+                }
+                for (slot, seen) in [(2, &at_barrier), (3, &after_barrier)] {
+                    s.spawn(move || {
+                        scheduler.wait_for_turn(slot);
+                        if slot == 2 {
+                            scheduler.wait_for_completed(2);
+                        }
+                        scheduler.start(slot);
+                        seen.store(finished.load(Ordering::SeqCst), Ordering::SeqCst);
+                        scheduler.finish(slot);
+                    });
+                }
+            });
+            assert_eq!(2, at_barrier.into_inner(), "{:?}", kind);
+            assert_eq!(2, after_barrier.into_inner(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_schedulers() {
+        let (order, _) = run(SchedulerKind::Strict, 4);
+        assert_eq!(vec![0, 1, 2, 3], order);
+
+        let (order, peak) = run(SchedulerKind::Pool { workers: 2 }, 6);
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], order);
+        assert!(peak <= 2);
+
+        // Bands of two: a band only starts once the previous one is done.
+        let (order, peak) = run(SchedulerKind::Bands { width: 2 }, 4);
+        assert!(order[..2].contains(&0) && order[..2].contains(&1));
+        assert!(peak <= 2);
+
+        assert_eq!(None, SchedulerKind::parse("pool:0"));
+        assert_eq!(
+            Some(SchedulerKind::Rate { per_second: 2.5 }),
+            SchedulerKind::parse("rate:2.5")
+        );
+    }
+}
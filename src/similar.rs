@@ -0,0 +1,126 @@
+use std::fmt;
+
+// The edit distance (insertions, deletions and substitutions of characters)
+// between `a` and `b`, or None once it is sure to be over `max`. Only the band
+// of `max` cells either side of the diagonal is filled in, so a full pass is
+// O(len * max) rather than O(len * len).
+pub fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // Stands in for every cell outside the band.
+    let far = max + 1;
+    let mut previous: Vec<usize> = (0..=b.len()).map(|j| j.min(far)).collect();
+    let mut current = vec![far; b.len() + 1];
+    for i in 1..=a.len() {
+        let (from, to) = (i.saturating_sub(max).max(1), (i + max).min(b.len()));
+        current.fill(far);
+        current[0] = i.min(far);
+        for j in from..=to {
+            let substitute = previous[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let cost = substitute.min(previous[j] + 1).min(current[j - 1] + 1);
+            current[j] = cost.min(far);
+        }
+        if current.iter().all(|&cost| cost > max) {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+// Pairs of keys within a few edits of each other: likely the same person
+// typed two ways. Nothing is merged; deciding is left to whoever reads it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimilarKeys {
+    pub max_distance: usize,
+    // (first, second, distance) with first < second, closest first, then by key.
+    pub pairs: Vec<(String, String, usize)>,
+}
+
+impl SimilarKeys {
+    pub fn find(mut keys: Vec<String>, max_distance: usize) -> Self {
+        // By length, so each key is only compared with the ones that could be
+        // close enough.
+        keys.sort_by(|a, b| a.chars().count().cmp(&b.chars().count()).then(a.cmp(b)));
+        let lengths: Vec<usize> = keys.iter().map(|key| key.chars().count()).collect();
+        let mut pairs = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            for (j, other) in keys.iter().enumerate().skip(i + 1) {
+                if lengths[j] - lengths[i] > max_distance {
+                    break;
+                }
+                if let Some(distance) = edit_distance(key, other, max_distance) {
+                    let (first, second) = if key < other {
+                        (key, other)
+                    } else {
+                        (other, key)
+                    };
+                    pairs.push((first.clone(), second.clone(), distance));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| {
+            a.2.cmp(&b.2)
+                .then_with(|| a.0.cmp(&b.0).then(a.1.cmp(&b.1)))
+        });
+        SimilarKeys {
+            max_distance,
+            pairs,
+        }
+    }
+}
+
+impl fmt::Display for SimilarKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pairs.is_empty() {
+            return write!(f, "No similar keys (max distance {})", self.max_distance);
+        }
+        write!(f, "Similar keys (max distance {}):", self.max_distance)?;
+        for (first, second, distance) in self.pairs.iter() {
+            write!(f, "\n{} ~ {} ({})", first, second, distance)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, SimilarKeys};
+
+    #[test]
+    fn test_similar_keys() {
+        assert_eq!(Some(1), edit_distance("Jon Smith", "John Smith", 2));
+        assert_eq!(Some(3), edit_distance("kitten", "sitting", 3));
+        assert_eq!(None, edit_distance("kitten", "sitting", 2));
+        assert_eq!(Some(0), edit_distance("", "", 0));
+        assert_eq!(None, edit_distance("a", "abcd", 2));
+
+        let keys = [
+            "John Smith",
+            "Jon Smith",
+            "Jane Roe",
+            "Jan Rowe",
+            "Bob",
+            "Smith",
+        ];
+        let report = SimilarKeys::find(keys.map(str::to_string).to_vec(), 2);
+        let pairs: Vec<(&str, &str, usize)> = report
+            .pairs
+            .iter()
+            .map(|(a, b, d)| (a.as_str(), b.as_str(), *d))
+            .collect();
+        assert_eq!(
+            vec![("John Smith", "Jon Smith", 1), ("Jan Rowe", "Jane Roe", 2)],
+            pairs
+        );
+        assert!(report
+            .to_string()
+            .starts_with("Similar keys (max distance 2):\nJohn Smith ~ Jon Smith (1)"));
+        assert_eq!(
+            "No similar keys (max distance 0)",
+            SimilarKeys::find(vec!["a".to_string()], 0).to_string()
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::logger::LockType;
+
+// One thread's use of one lock: blocked from `requested`, holding from
+// `acquired` until `released`.
+pub struct Span {
+    pub thread: String,
+    pub lock: String,
+    pub lock_type: LockType,
+    pub requested: Instant,
+    pub acquired: Instant,
+    pub released: Instant,
+}
+
+// Every span in the run, drawn at the end as an ASCII chart.
+pub struct Timeline {
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Timeline::new()
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline {
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, span: Span) {
+        self.spans.lock().unwrap().push(span);
+    }
+
+    // One row per thread and lock, in the order they were first requested, with
+    // the run split into `columns` equal slices: `-` while blocked, `R` or `W`
+    // while holding.
+    pub fn render(&self, columns: usize) -> String {
+        let mut spans = self.spans.lock().unwrap();
+        spans.sort_by_key(|span| span.requested);
+        let (Some(start), Some(end)) = (
+            spans.first().map(|span| span.requested),
+            spans.iter().map(|span| span.released).max(),
+        ) else {
+            return "Timeline: no lock activity".to_string();
+        };
+
+        let total = (end - start).as_nanos().max(1);
+        let column = |at: Instant| ((at - start).as_nanos() * columns as u128 / total) as usize;
+        let column = |at: Instant| column(at).min(columns - 1);
+
+        let mut rows: Vec<(String, Vec<u8>)> = Vec::new();
+        for span in spans.iter() {
+            let label = format!("{} {}", span.thread, span.lock);
+            let cells = match rows.iter().position(|(l, _)| *l == label) {
+                Some(i) => &mut rows[i].1,
+                None => {
+                    rows.push((label, vec![b' '; columns]));
+                    &mut rows.last_mut().unwrap().1
+                }
+            };
+            for cell in &mut cells[column(span.requested)..column(span.acquired)] {
+                if *cell == b' ' {
+                    *cell = b'-';
+                }
+            }
+            let held = match span.lock_type {
+                LockType::Read => b'R',
+                LockType::Write => b'W',
+            };
+            for cell in &mut cells[column(span.acquired)..=column(span.released)] {
+                *cell = held;
+            }
+        }
+
+        let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let mut out = format!(
+            "Timeline ({}us per column; - blocked, R/W holding):",
+            total / 1_000 / columns as u128
+        );
+        for (label, cells) in rows {
+            // Only ever ASCII.
+            let cells = String::from_utf8(cells).unwrap();
+            out.push_str(&format!("\n{:<width$} |{}|", label, cells, width = width));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Span, Timeline};
+    use crate::logger::LockType;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_render() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let timeline = Timeline::new();
+        timeline.record(Span {
+            thread: "2".to_string(),
+            lock: "table".to_string(),
+            lock_type: LockType::Read,
+            requested: at(2),
+            acquired: at(6),
+            released: at(9),
+        });
+        timeline.record(Span {
+            thread: "1".to_string(),
+            lock: "table".to_string(),
+            lock_type: LockType::Write,
+            requested: at(0),
+            acquired: at(0),
+            released: at(5),
+        });
+
+        assert_eq!(
+            "Timeline (900us per column; - blocked, R/W holding):\n\
+             1 table |WWWWWW    |\n\
+             2 table |  ----RRRR|",
+            timeline.render(10)
+        );
+    }
+}
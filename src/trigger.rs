@@ -0,0 +1,144 @@
+use crate::money::Money;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+// A single-key mutation that is about to run.
+pub struct TriggerEvent<'a, K = String> {
+    pub kind: TriggerKind,
+    pub name: &'a K,
+}
+
+// Operations a trigger queues up to run once the triggering one has committed.
+// They are applied without firing triggers again, so triggers cannot loop.
+#[derive(Debug, Clone)]
+pub enum FollowUp<K = String, V = Money> {
+    Insert { name: K, salary: V },
+    Adjust { name: K, delta: Money },
+}
+
+pub enum TriggerAction<K = String, V = Money> {
+    Continue,
+    Veto { reason: String },
+    Then(Vec<FollowUp<K, V>>),
+}
+
+type Hook<K, V> = Box<dyn Fn(&TriggerEvent<K>) -> TriggerAction<K, V> + Send + Sync>;
+
+// Hooks run before the table lock is taken, and follow-ups after it is
+// released, so a hook may safely look at the table itself.
+pub struct Triggers<K = String, V = Money> {
+    on_insert: Vec<Hook<K, V>>,
+    on_update: Vec<Hook<K, V>>,
+    on_delete: Vec<Hook<K, V>>,
+}
+
+impl<K, V> Default for Triggers<K, V> {
+    fn default() -> Self {
+        Triggers {
+            on_insert: Vec::new(),
+            on_update: Vec::new(),
+            on_delete: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> Triggers<K, V> {
+    pub fn on_insert(
+        &mut self,
+        hook: impl Fn(&TriggerEvent<K>) -> TriggerAction<K, V> + Send + Sync + 'static,
+    ) {
+        self.on_insert.push(Box::new(hook));
+    }
+
+    pub fn on_update(
+        &mut self,
+        hook: impl Fn(&TriggerEvent<K>) -> TriggerAction<K, V> + Send + Sync + 'static,
+    ) {
+        self.on_update.push(Box::new(hook));
+    }
+
+    pub fn on_delete(
+        &mut self,
+        hook: impl Fn(&TriggerEvent<K>) -> TriggerAction<K, V> + Send + Sync + 'static,
+    ) {
+        self.on_delete.push(Box::new(hook));
+    }
+
+    fn hooks(&self, kind: TriggerKind) -> &[Hook<K, V>] {
+        match kind {
+            TriggerKind::Insert => &self.on_insert,
+            TriggerKind::Update => &self.on_update,
+            TriggerKind::Delete => &self.on_delete,
+        }
+    }
+
+    // Whether any hook runs for `kind`, so the table need not build an event
+    // for nothing.
+    pub fn watches(&self, kind: TriggerKind) -> bool {
+        !self.hooks(kind).is_empty()
+    }
+
+    // Runs the hooks for `event` in registration order. The first veto wins;
+    // otherwise every hook's follow-ups are returned together.
+    pub fn fire(&self, event: &TriggerEvent<K>) -> Result<Vec<FollowUp<K, V>>, String> {
+        let hooks = self.hooks(event.kind);
+
+        let mut follow_ups = Vec::new();
+        for hook in hooks {
+            match hook(event) {
+                TriggerAction::Continue => {}
+                TriggerAction::Veto { reason } => return Err(reason),
+                TriggerAction::Then(more) => follow_ups.extend(more),
+            }
+        }
+        Ok(follow_ups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FollowUp, TriggerAction, TriggerEvent, TriggerKind, Triggers};
+
+    #[test]
+    fn test_fire() {
+        let mut triggers: Triggers = Triggers::default();
+        triggers.on_delete(|event| match event.name.as_str() {
+            "Boss" => TriggerAction::Veto {
+                reason: "protected".to_string(),
+            },
+            _ => TriggerAction::Continue,
+        });
+        triggers.on_delete(|event| {
+            TriggerAction::Then(vec![FollowUp::Insert {
+                name: format!("{} (former)", event.name),
+                salary: "0".parse().unwrap(),
+            }])
+        });
+
+        let (boss, link) = ("Boss".to_string(), "Link".to_string());
+        let delete = |name| TriggerEvent {
+            kind: TriggerKind::Delete,
+            name,
+        };
+        assert_eq!(
+            Err("protected".to_string()),
+            triggers.fire(&delete(&boss)).map(|_| ())
+        );
+        assert!(!triggers.watches(TriggerKind::Update));
+        let follow_ups = triggers.fire(&delete(&link)).unwrap();
+        assert!(
+            matches!(&follow_ups[..], [FollowUp::Insert { name, .. }] if name == "Link (former)")
+        );
+
+        let insert = TriggerEvent {
+            kind: TriggerKind::Insert,
+            name: &boss,
+        };
+        assert!(triggers.fire(&insert).unwrap().is_empty());
+    }
+}
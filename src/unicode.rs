@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::unicode_tables::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS};
+
+// Set by --normalize-keys.
+static NORMALIZE_KEYS: AtomicBool = AtomicBool::new(false);
+
+// Hangul syllables are composed by arithmetic rather than from the tables.
+const S_BASE: u32 = 0xac00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11a7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+// Puts keys in NFC from now on, wherever input is parsed, so keys typed in
+// different normalization forms land on one record.
+pub fn set_normalize_keys(on: bool) {
+    NORMALIZE_KEYS.store(on, Ordering::Relaxed);
+}
+
+// A line of input with its keys in NFC, if --normalize-keys is on. A comma
+// neither composes nor reorders with its neighbours, so normalizing a whole
+// line normalizes each of its fields.
+pub fn normalize_keys(line: &str) -> Cow<'_, str> {
+    match NORMALIZE_KEYS.load(Ordering::Relaxed) {
+        true => nfc(line),
+        false => Cow::Borrowed(line),
+    }
+}
+
+// Unicode Normalization Form C: canonical decomposition, canonical ordering,
+// then canonical composition.
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    // Below U+0300 nothing decomposes or combines.
+    if s.chars().all(|c| c < '\u{300}') {
+        return Cow::Borrowed(s);
+    }
+    let mut chars = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        decompose(c, &mut chars);
+    }
+    reorder(&mut chars);
+    let composed: String = compose(chars).into_iter().collect();
+    match composed == s {
+        true => Cow::Borrowed(s),
+        false => Cow::Owned(composed),
+    }
+}
+
+fn decompose(c: char, out: &mut Vec<char>) {
+    let s = c as u32;
+    if (S_BASE..S_BASE + S_COUNT).contains(&s) {
+        let index = s - S_BASE;
+        out.push(char::from_u32(L_BASE + index / N_COUNT).unwrap());
+        out.push(char::from_u32(V_BASE + index % N_COUNT / T_COUNT).unwrap());
+        if !index.is_multiple_of(T_COUNT) {
+            out.push(char::from_u32(T_BASE + index % T_COUNT).unwrap());
+        }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(c, _)| c) {
+        Ok(i) => DECOMPOSITIONS[i].1.iter().for_each(|&c| decompose(c, out)),
+        Err(_) => out.push(c),
+    }
+}
+
+fn combining_class(c: char) -> u8 {
+    match COMBINING_CLASSES.binary_search_by(|&(first, last, _)| {
+        if last < c {
+            std::cmp::Ordering::Less
+        } else if first > c {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }) {
+        Ok(i) => COMBINING_CLASSES[i].2,
+        Err(_) => 0,
+    }
+}
+
+// Sorts each run of combining marks by class, keeping the order of marks in
+// the same class.
+fn reorder(chars: &mut [char]) {
+    let mut start = 0;
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let end = chars[start..]
+            .iter()
+            .position(|&c| combining_class(c) == 0)
+            .map_or(chars.len(), |len| start + len);
+        chars[start..end].sort_by_key(|&c| combining_class(c));
+        start = end;
+    }
+}
+
+fn compose(chars: Vec<char>) -> Vec<char> {
+    let mut out: Vec<char> = Vec::with_capacity(chars.len());
+    // The last starter, which later characters may combine with.
+    let mut starter: Option<usize> = None;
+    for c in chars {
+        let class = combining_class(c);
+        if let Some(at) = starter {
+            // Blocked by anything in between of class 0 or of at least `c`'s.
+            let last = combining_class(*out.last().unwrap());
+            let adjacent = out.len() == at + 1;
+            if adjacent || last < class {
+                if let Some(composite) = pair(out[at], c) {
+                    out[at] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+        }
+        out.push(c);
+    }
+    out
+}
+
+// The primary composite of `first` followed by `second`, if there is one.
+fn pair(first: char, second: char) -> Option<char> {
+    let (l, v) = (first as u32, second as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&l) && (V_BASE..V_BASE + V_COUNT).contains(&v) {
+        let index = (l - L_BASE) * N_COUNT + (v - V_BASE) * T_COUNT;
+        return char::from_u32(S_BASE + index);
+    }
+    let t = v;
+    if (S_BASE..S_BASE + S_COUNT).contains(&l)
+        && (l - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&t)
+    {
+        return char::from_u32(l + t - T_BASE);
+    }
+    COMPOSITIONS
+        .binary_search_by_key(&(first, second), |&(a, b, _)| (a, b))
+        .ok()
+        .map(|i| COMPOSITIONS[i].2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nfc;
+
+    #[test]
+    fn test_nfc() {
+        assert_eq!("\u{e9}", nfc("e\u{301}"));
+        assert_eq!("\u{e9}", nfc("\u{e9}"));
+        assert_eq!("Zo\u{eb} \u{3a9}", nfc("Zoe\u{308} \u{2126}"));
+        // Marks are put in class order before composing: the cedilla (202)
+        // goes first and composes, the acute (230) then composes too.
+        assert_eq!("\u{1e08}", nfc("C\u{301}\u{327}"));
+        // The dot below composes past the circumflex, which then composes too.
+        assert_eq!("\u{1ead}", nfc("a\u{302}\u{323}"));
+        // Composition exclusions stay decomposed.
+        assert_eq!("\u{915}\u{93c}", nfc("\u{958}"));
+        // Hangul, by arithmetic.
+        assert_eq!("\u{d55c}", nfc("\u{1112}\u{1161}\u{11ab}"));
+        assert_eq!("\u{d55c}", nfc("\u{d55c}"));
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::cdc::Change;
+use crate::hash_table::{HashRecord, Value};
+use crate::money::{Money, OverflowPolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reducer {
+    // Records per group.
+    Count,
+    // Total salary per group, one total per currency. Values that are not
+    // money add nothing.
+    Sum,
+}
+
+impl Reducer {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "count" => Some(Reducer::Count),
+            "sum" => Some(Reducer::Sum),
+            _ => None,
+        }
+    }
+}
+
+// One group's running totals. Both are kept whatever the reducer, so a group
+// can be dropped as soon as its last record goes.
+#[derive(Debug, Clone, Default)]
+pub struct Aggregate {
+    count: usize,
+    totals: Vec<Money>,
+}
+
+impl Aggregate {
+    fn add(&mut self, salary: Option<Money>) {
+        self.count += 1;
+        let Some(salary) = salary else {
+            return;
+        };
+        match self
+            .totals
+            .iter_mut()
+            .find(|t| t.currency() == salary.currency())
+        {
+            Some(total) => *total = total.add(salary, OverflowPolicy::Saturating).unwrap(),
+            None => self.totals.push(salary),
+        }
+    }
+
+    fn remove(&mut self, salary: Option<Money>) {
+        self.count -= 1;
+        let Some(salary) = salary else {
+            return;
+        };
+        if let Some(total) = self
+            .totals
+            .iter_mut()
+            .find(|t| t.currency() == salary.currency())
+        {
+            *total = total
+                .add(salary.negated(), OverflowPolicy::Saturating)
+                .unwrap();
+        }
+    }
+}
+
+// A view's row for one group, rendered with the view's reducer.
+pub struct ViewRow {
+    pub group: String,
+    pub reducer: Reducer,
+    pub aggregate: Aggregate,
+}
+
+impl fmt::Display for ViewRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reducer {
+            Reducer::Count => write!(f, "{}: {}", self.group, self.aggregate.count),
+            Reducer::Sum => {
+                let totals: Vec<String> = self
+                    .aggregate
+                    .totals
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect();
+                write!(f, "{}: {}", self.group, totals.join(" + "))
+            }
+        }
+    }
+}
+
+type GroupBy<K, V> = Box<dyn Fn(&HashRecord<K, V>) -> String + Send + Sync>;
+
+// An aggregate over groups of records, updated from every committed change
+// instead of rescanning the table.
+pub struct View<K = String, V = Money> {
+    name: String,
+    group_by: GroupBy<K, V>,
+    reducer: Reducer,
+    // Only written while the table's write lock is held.
+    groups: Mutex<BTreeMap<String, Aggregate>>,
+}
+
+impl<K, V: Value> View<K, V> {
+    pub fn new(
+        name: &str,
+        group_by: impl Fn(&HashRecord<K, V>) -> String + Send + Sync + 'static,
+        reducer: Reducer,
+    ) -> Self {
+        View {
+            name: name.to_string(),
+            group_by: Box::new(group_by),
+            reducer,
+            groups: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn apply(&self, change: &Change<K, V>) {
+        let mut groups = self.groups.lock().unwrap();
+        let mut remove = |record: &HashRecord<K, V>| {
+            let group = (self.group_by)(record);
+            if let Some(aggregate) = groups.get_mut(&group) {
+                aggregate.remove(record.salary.money());
+                if aggregate.count == 0 {
+                    groups.remove(&group);
+                }
+            }
+        };
+        match change {
+            Change::Insert { .. } => {}
+            Change::Update { old_record, .. } => remove(old_record),
+            Change::Delete { record } => remove(record),
+        }
+        match change {
+            Change::Insert { record }
+            | Change::Update {
+                new_record: record, ..
+            } => groups
+                .entry((self.group_by)(record))
+                .or_default()
+                .add(record.salary.money()),
+            Change::Delete { .. } => {}
+        }
+    }
+
+    // Groups in sorted order.
+    pub fn rows(&self) -> Vec<ViewRow> {
+        self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(group, aggregate)| ViewRow {
+                group: group.clone(),
+                reducer: self.reducer,
+                aggregate: aggregate.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reducer, View};
+    use crate::cdc::Change;
+    use crate::hash_table::HashRecord;
+
+    fn record(name: &str, salary: &str) -> HashRecord {
+        HashRecord {
+            hash: 0,
+            name: name.to_string(),
+            salary: salary.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_incremental_sum() {
+        let view = View::new(
+            "by_initial",
+            |r: &HashRecord| r.name[..1].to_string(),
+            Reducer::Sum,
+        );
+        for change in [
+            Change::Insert {
+                record: record("Ann", "100"),
+            },
+            Change::Insert {
+                record: record("Amy", "50 EUR"),
+            },
+            Change::Insert {
+                record: record("Bob", "10"),
+            },
+            Change::Update {
+                old_record: record("Ann", "100"),
+                new_record: record("Ann", "120.50"),
+            },
+            Change::Delete {
+                record: record("Bob", "10"),
+            },
+        ] {
+            view.apply(&change);
+        }
+
+        let rows: Vec<String> = view.rows().iter().map(|r| r.to_string()).collect();
+        assert_eq!(vec!["A: 120.50 + 50 EUR"], rows);
+    }
+}
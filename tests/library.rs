@@ -0,0 +1,69 @@
+// The table as another crate sees it: only what lib.rs exports.
+use concurrent_hash_table::{
+    ConflictPolicy, DeleteResult, HashTable, InsertResult, SalaryTable, SearchResult, ThreadLogger,
+    UpdateResult,
+};
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn test_shared_between_threads() {
+    let path = std::env::temp_dir().join("cht_test_library.log");
+    let logger = Arc::new(ThreadLogger::new(path.to_str().unwrap()));
+    let table = Arc::new(SalaryTable::with_logger(logger));
+
+    let handles: Vec<_> = (0..4)
+        .map(|worker| {
+            let table = Arc::clone(&table);
+            thread::spawn(move || {
+                for i in 0..25 {
+                    let name = format!("w{}-{}", worker, i);
+                    let result = table.insert(&name, "100".parse().unwrap(), worker);
+                    assert!(matches!(result, InsertResult::Success { .. }));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(100, table.count(0));
+
+    assert!(matches!(
+        table.update_salary("w2-7", "150".parse().unwrap(), 0),
+        UpdateResult::Success { .. }
+    ));
+    assert!(matches!(
+        table.search("w2-7", 0),
+        SearchResult::Found { record } if record.salary == "150".parse().unwrap()
+    ));
+    assert!(matches!(
+        table.delete("w2-7", 0),
+        DeleteResult::Success { .. }
+    ));
+    assert!(matches!(
+        table.delete("w2-7", 0),
+        DeleteResult::NotFound { .. }
+    ));
+    assert_eq!(99, table.all_records(0).len());
+
+    // Dropping the last reference flushes the log.
+    drop(table);
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(log.lines().any(|line| line.contains("DELETE,")));
+}
+
+#[test]
+fn test_generic_table() {
+    let mut table: HashTable<u64, u64> = HashTable::new();
+    table.set_conflict_policy(ConflictPolicy::Merge(|old, new| old + new));
+    for id in 0..10 {
+        table.insert(&id, 1, 0);
+        table.insert(&id, 2, 0);
+    }
+    assert_eq!(10, table.count(0));
+    assert!(matches!(
+        table.search(&3, 0),
+        SearchResult::Found { record } if record.salary == 3
+    ));
+}